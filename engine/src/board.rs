@@ -1,6 +1,12 @@
+use crate::crazyhouse::Pocket;
 use crate::threat::{King, ThreatMask};
+use crate::variant::Variant;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The number of Chess960 starting positions
+pub const CHESS960_POSITIONS: u32 = 960;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Color {
     White,
     Black,
@@ -166,6 +172,13 @@ impl core::str::FromStr for Coord {
     }
 }
 
+impl std::fmt::Display for Coord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (x, y) = self.as_xy();
+        write!(f, "{}{}", (b'a' + x as u8) as char, y + 1)
+    }
+}
+
 impl UnsafeCoord {
     pub const fn baseline(&self) -> Option<Color> {
         match self.0 {
@@ -248,6 +261,13 @@ pub struct Board {
     pub(crate) threat_mask: ThreatMask,
     pub(crate) black_king: King,
     pub(crate) white_king: King,
+    /// The number of half moves since the start of the game
+    pub(crate) ply: u32,
+    pub(crate) variant: Variant,
+    /// The pockets of white and black in Crazyhouse
+    pub(crate) pockets: [Pocket; 2],
+    /// The squares of the promoted pieces in Crazyhouse, one bit for each
+    pub(crate) promoted: u64,
 }
 
 impl Board {
@@ -290,11 +310,113 @@ impl Board {
             threat_mask: ThreatMask::new(),
             black_king: King::new(Color::Black),
             white_king: King::new(Color::White),
+            ply: 0,
+            variant: Variant::Standard,
+            pockets: [Pocket::default(); 2],
+            promoted: 0,
         };
         self_empty_threat_mask.init_threat_mask();
         self_empty_threat_mask
     }
 
+    pub const fn ply(&self) -> u32 {
+        self.ply
+    }
+
+    /// The color to move, which follows from the number of half moves
+    pub const fn color_to_move(&self) -> Color {
+        if self.ply % 2 == 0 {
+            Color::White
+        } else {
+            Color::Black
+        }
+    }
+
+    pub const fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        if variant == Variant::Antichess {
+            for king in [&mut self.white_king, &mut self.black_king].iter_mut() {
+                king.castling_to_left = None;
+                king.castling_to_right = None;
+            }
+        }
+        // what counts as a check depends on the variant
+        self.update_aggressors(Color::White);
+        self.update_aggressors(Color::Black);
+    }
+
+    /// The castling rights in the order `KQkq`, where `K` is castling with the rook on the
+    /// right of the king, a right only counts if the king and the rook are still on the
+    /// first rank
+    pub fn castling_rights(&self) -> [bool; 4] {
+        let right = |king: &King, file: Option<i8>, y, rook| {
+            king.coord.as_xy().1 == y
+                && matches!(file, Some(x) if *self.get(Coord::from_xy(x, y)) == rook)
+        };
+        let (white_rook, black_rook) = (
+            Field::WhitePiece(Piece::Rook),
+            Field::BlackPiece(Piece::Rook),
+        );
+        let (white, black) = (&self.white_king, &self.black_king);
+        [
+            right(white, white.castling_to_right, 0, white_rook),
+            right(white, white.castling_to_left, 0, white_rook),
+            right(black, black.castling_to_right, 7, black_rook),
+            right(black, black.castling_to_left, 7, black_rook),
+        ]
+    }
+
+    /// The Chess960 starting position with the number `n` below 960 in the numbering of
+    /// Scharnagl, number 518 is the classical one
+    pub fn chess960(n: u32) -> Option<Self> {
+        if n >= CHESS960_POSITIONS {
+            return None;
+        }
+        let mut rank = [None; 8];
+        let (b1, n) = (n % 4, n / 4);
+        let (b2, n) = (n % 4, n / 4);
+        let (q, n) = (n % 6, n / 6);
+        rank[b1 as usize * 2 + 1] = Some('b');
+        rank[b2 as usize * 2] = Some('b');
+        let mut place = |nth: u32, piece| {
+            let x = (0..8)
+                .filter(|&x| rank[x].is_none())
+                .nth(nth as usize)
+                .unwrap();
+            rank[x] = Some(piece);
+        };
+        place(q, 'q');
+        // the knights take two of the five remaining squares
+        let (n1, n2) = [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (1, 1),
+            (1, 2),
+            (1, 3),
+            (2, 2),
+            (2, 3),
+            (3, 3),
+        ][n as usize];
+        place(n1, 'n');
+        place(n2, 'n');
+        for &piece in &['r', 'k', 'r'] {
+            place(0, piece);
+        }
+        let black: String = rank.iter().map(|p| p.unwrap()).collect();
+        let fen = format!(
+            "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+            black,
+            black.to_ascii_uppercase()
+        );
+        Self::from_fen(&fen).ok().map(|(board, _)| board)
+    }
+
     pub fn get<C: CommonCoord>(&self, coord: C) -> &Field {
         unsafe { self.data.get_unchecked(coord.raw() as usize) }
     }
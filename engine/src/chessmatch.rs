@@ -1,25 +1,147 @@
 use crate::board::{Board, Color};
+use crate::clock::{Clock, TimeControl};
+use crate::decision::{Config, Observer, SearchProgress, SearchResult, DEFAULT_CONFIG};
 use crate::moves::{LongMoveList, Move};
-use std::sync::{Arc, RwLock};
+use crate::score::Score;
+use crate::storage::{ClockRecord, MatchRecord, Storage, StorageError, StorageErrorHandler};
+use crate::variant::Variant;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy)]
+/// The computer accepts a draw unless it is ahead by more than a pawn divided by this
+const DRAW_ACCEPT_DIVISOR: i32 = 5;
+/// The time the computer may think about a draw offer
+const DRAW_OFFER_TIME: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum MatchResult {
     WhiteWins,
     BlackWins,
     Stalemate,
+    /// The king of the color reached the hill
+    KingOfTheHill(Color),
+    /// The color gave the third check
+    ThreeChecks(Color),
+    /// The color exploded the enemy king
+    Explosion(Color),
+    /// The color cannot move any more, which wins Antichess
+    OutOfMoves(Color),
+    /// The opponent of the color ran out of time
+    Timeout(Color),
+    /// The opponent of the color resigned
+    Resignation(Color),
+    /// The players agreed to a draw
+    DrawByAgreement,
+}
+
+/// A move of the match and the position after it
+#[derive(Clone)]
+pub struct PlayedMove {
+    pub mv: Move,
+    /// The move in standard algebraic notation
+    pub san: String,
+    pub board: Board,
+}
+
+/// The wish of the color to take back the last half moves, which the opponent has to approve
+#[derive(Debug, Clone, Copy)]
+pub struct TakebackRequest {
+    pub color: Color,
+    pub plies: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UndoError {
+    UnknownMatch,
+    MatchOver,
+    NotEnoughMoves,
+    NoRequest,
+}
+
+impl std::fmt::Display for UndoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownMatch => write!(f, "there is no such match"),
+            Self::MatchOver => write!(f, "the match is already over"),
+            Self::NotEnoughMoves => write!(f, "not enough moves to take back"),
+            Self::NoRequest => write!(f, "the opponent did not ask for a takeback"),
+        }
+    }
+}
+
+impl std::error::Error for UndoError {}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DrawError {
+    UnknownMatch,
+    MatchOver,
+    NoOffer,
 }
 
+impl std::fmt::Display for DrawError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownMatch => write!(f, "there is no such match"),
+            Self::MatchOver => write!(f, "the match is already over"),
+            Self::NoOffer => write!(f, "the opponent did not offer a draw"),
+        }
+    }
+}
+
+impl std::error::Error for DrawError {}
+
 #[derive(Clone)]
 pub struct MatchInfos<E: Clone + Send + Sync> {
     pub result: Option<MatchResult>,
     pub color: Color,
+    /// The result of the last search of the computer
+    pub last_search: Option<SearchResult>,
+    /// The progress of the computer while it is deciding on a move
+    pub thinking: Option<SearchProgress>,
+    /// The clock of a match with a time control
+    pub clock: Option<Clock>,
+    /// The position the match started from
+    pub start: Board,
+    pub history: Vec<PlayedMove>,
+    pub takeback: Option<TakebackRequest>,
+    /// The color that offered a draw, which stands until the opponent moves
+    pub draw_offer: Option<Color>,
     pub extra: E,
 }
 
+/// A search of the computer on the opponent's time
+struct Ponder {
+    /// The reply of the opponent that the search assumes
+    expected: Move,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Option<SearchResult>>,
+}
+
+impl Ponder {
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed)
+    }
+}
+
+/// Where the registry keeps its matches and whom it tells about failures to do so
+struct Persistence<E> {
+    storage: Arc<dyn Storage<E>>,
+    on_error: StorageErrorHandler,
+}
+
 pub struct MatchRegistry<E: Clone + Send + Sync> {
-    empty_slots: Arc<RwLock<Vec<u32>>>,
-    boards: Arc<RwLock<Vec<Board>>>,
-    infos: Arc<RwLock<Vec<MatchInfos<E>>>>,
+    boards: Arc<RwLock<HashMap<u32, Board>>>,
+    infos: Arc<RwLock<HashMap<u32, MatchInfos<E>>>>,
+    config: Arc<RwLock<Config>>,
+    ponders: Arc<Mutex<HashMap<u32, Ponder>>>,
+    /// Stops the search of the computer for its next move, e.g. after a takeback
+    searches: Arc<Mutex<HashMap<u32, Arc<AtomicBool>>>>,
+    /// Keeps the matches across restarts, they are written after every change
+    storage: Option<Arc<Persistence<E>>>,
 }
 
 impl<E: Clone + Send + Sync + 'static> Default for MatchRegistry<E> {
@@ -31,84 +153,703 @@ impl<E: Clone + Send + Sync + 'static> Default for MatchRegistry<E> {
 impl<E: Clone + Send + Sync + 'static> MatchRegistry<E> {
     pub fn new() -> Self {
         Self {
-            empty_slots: Arc::new(RwLock::new(vec![])),
-            boards: Arc::new(RwLock::new(vec![])),
-            infos: Arc::new(RwLock::new(vec![])),
+            boards: Arc::new(RwLock::new(HashMap::new())),
+            infos: Arc::new(RwLock::new(HashMap::new())),
+            config: Arc::new(RwLock::new(DEFAULT_CONFIG)),
+            ponders: Arc::new(Mutex::new(HashMap::new())),
+            searches: Arc::new(Mutex::new(HashMap::new())),
+            storage: None,
+        }
+    }
+
+    /// Continue the matches of `storage` and keep all changes in it
+    ///
+    /// A match that cannot be loaded is handed to `on_error` and left out, its id is given to
+    /// a new match. So are the errors of writing a match later on.
+    pub fn with_storage(
+        storage: Arc<dyn Storage<E>>,
+        on_error: StorageErrorHandler,
+    ) -> Result<Self, StorageError> {
+        let mut registry = Self::new();
+        {
+            let mut boards = registry.boards.write().unwrap();
+            let mut infos = registry.infos.write().unwrap();
+            for (id, record) in storage.load_all()? {
+                match record.and_then(|r| Self::restore(r).ok_or(StorageError::InvalidRecord(id))) {
+                    Ok((board, info)) => {
+                        boards.insert(id, board);
+                        infos.insert(id, info);
+                    }
+                    Err(e) => on_error.as_ref()(id, e),
+                }
+            }
         }
+        registry.storage = Some(Arc::new(Persistence { storage, on_error }));
+        Ok(registry)
+    }
+
+    /// Let the computer think with the configuration of `computer` in the matches where it is
+    /// to move, which is needed after loading them from the storage
+    pub fn resume_computers<F: Fn(&E, Color) -> Option<Config>>(&self, computer: F) {
+        let boards = self.boards.read().unwrap();
+        let infos = self.infos.read().unwrap();
+        for (&id, i) in infos.iter() {
+            let last_move = match i.history.last() {
+                Some(played) if i.result.is_none() => played.mv,
+                _ => continue,
+            };
+            if let Some(mut config) = computer(&i.extra, i.color) {
+                if let Some(clock) = &i.clock {
+                    clock.apply(i.color, &mut config);
+                }
+                self.spawn_decision_maker(id, i.color, &boards[&id], last_move, None, config);
+            }
+        }
+    }
+
+    /// The infos of a match from `board` with white to move
+    fn new_infos(board: Board, clock: Option<Clock>, extra: E) -> MatchInfos<E> {
+        MatchInfos {
+            result: None,
+            color: Color::White,
+            last_search: None,
+            thinking: None,
+            clock,
+            start: board,
+            history: vec![],
+            takeback: None,
+            draw_offer: None,
+            extra,
+        }
+    }
+
+    /// Replay the moves of `record` from its starting position
+    fn restore(record: MatchRecord<E>) -> Option<(Board, MatchInfos<E>)> {
+        let (mut board, _) = Board::from_fen(&record.start).ok()?;
+        board.set_variant(record.variant);
+        let mut info = Self::new_infos(board.clone(), None, record.extra);
+        for san in &record.moves {
+            let mv = board.parse_san(info.color, san).ok()?;
+            Self::play(&mut board, &mut info, mv);
+        }
+        info.result = record.result.or(info.result);
+        let running = match info.result {
+            None if !info.history.is_empty() => Some(info.color),
+            _ => None,
+        };
+        info.clock = record
+            .clock
+            .map(|c| Clock::resume(c.control, [c.white, c.black], running));
+        Some((board, info))
+    }
+
+    /// Write the match to the storage, a failure only loses the latest changes on a restart
+    fn store(storage: &Option<Arc<Persistence<E>>>, id: u32, info: &MatchInfos<E>) {
+        let persistence = match storage {
+            Some(persistence) => persistence,
+            None => return,
+        };
+        let record = MatchRecord {
+            start: info.start.to_fen(Color::White),
+            variant: info.start.variant(),
+            moves: info.history.iter().map(|m| m.san.clone()).collect(),
+            result: info.result,
+            clock: info.clock.as_ref().map(|clock| {
+                let [white, black] = clock.times();
+                ClockRecord {
+                    control: clock.control,
+                    white,
+                    black,
+                }
+            }),
+            extra: info.extra.clone(),
+        };
+        if let Err(e) = persistence.storage.save(id, &record) {
+            persistence.on_error.as_ref()(id, e);
+        }
+    }
+
+    /// Another handle to the same matches for the threads of the computer
+    fn shared(&self) -> Self {
+        Self {
+            boards: self.boards.clone(),
+            infos: self.infos.clone(),
+            config: self.config.clone(),
+            ponders: self.ponders.clone(),
+            searches: self.searches.clone(),
+            storage: self.storage.clone(),
+        }
+    }
+
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap() = config;
+    }
+
+    pub fn get_config(&self) -> Config {
+        self.config.read().unwrap().clone()
     }
 
     pub fn create_match(&self, extra: E) -> u32 {
+        self.create_match_from(Board::new(), None, extra)
+    }
+
+    /// Start a match from `board` with white to move, e.g. a Chess960 starting position,
+    /// whose clock starts with the first move if there is a `time_control`
+    pub fn create_match_from(
+        &self,
+        board: Board,
+        time_control: Option<TimeControl>,
+        extra: E,
+    ) -> u32 {
         let mut boards = self.boards.write().unwrap();
         let mut infos = self.infos.write().unwrap();
-        let (board, info) = (
-            Board::new(),
-            MatchInfos {
-                result: None,
-                color: Color::White,
-                extra,
-            },
-        );
-        (if self.empty_slots.read().unwrap().is_empty() {
-            boards.push(board);
-            infos.push(info);
-            boards.len() - 1
-        } else {
-            let id = self.empty_slots.write().unwrap().pop().unwrap() as usize;
-            boards[id] = board;
-            infos[id] = info;
-            id
-        }) as u32
+        let info = Self::new_infos(board.clone(), time_control.map(Clock::new), extra);
+        // the lowest free id, the ids of matches that could not be loaded are free as well
+        let id = (0..).find(|id| !infos.contains_key(id)).unwrap();
+        boards.insert(id, board);
+        Self::store(&self.storage, id, &info);
+        infos.insert(id, info);
+        id
     }
 
     pub fn get_board(&self, id: u32) -> Option<Board> {
-        self.boards.read().unwrap().get(id as usize).cloned()
+        self.boards.read().unwrap().get(&id).cloned()
+    }
+
+    /// Set the result once the game is over for `info.color`, which is to move on `board`
+    fn update_result(board: &Board, info: &mut MatchInfos<E>) {
+        if let Some(winner) = board.variant_winner() {
+            info.result = match board.variant() {
+                Variant::KingOfTheHill => Some(MatchResult::KingOfTheHill(winner)),
+                Variant::ThreeCheck => Some(MatchResult::ThreeChecks(winner)),
+                Variant::Atomic => Some(MatchResult::Explosion(winner)),
+                Variant::Standard | Variant::Antichess | Variant::Crazyhouse => None,
+            };
+            return;
+        }
+        let mut moves = LongMoveList::new();
+        board.enumerate_all_moves_by(info.color, &mut moves);
+        if moves.is_empty() {
+            info.result = Some(if board.variant() == Variant::Antichess {
+                MatchResult::OutOfMoves(info.color)
+            } else if board.get_king(info.color).aggressors.is_empty() {
+                MatchResult::Stalemate
+            } else if let Color::White = info.color {
+                MatchResult::BlackWins
+            } else {
+                MatchResult::WhiteWins
+            })
+        }
+    }
+
+    /// Set and store the timeout result if the flag of `info.color` has fallen, true if the
+    /// match is over
+    fn update_flag(
+        storage: &Option<Arc<Persistence<E>>>,
+        id: u32,
+        info: &mut MatchInfos<E>,
+    ) -> bool {
+        if let (None, Some(clock)) = (info.result, &mut info.clock) {
+            if clock.has_flag_fallen(info.color) {
+                clock.stop();
+                info.result = Some(MatchResult::Timeout(!info.color));
+                Self::store(storage, id, info);
+            }
+        }
+        info.result.is_some()
+    }
+
+    /// Press the clock after the move of the color that is no longer to move
+    fn press_clock(info: &mut MatchInfos<E>) {
+        if let Some(clock) = &mut info.clock {
+            if info.result.is_some() {
+                clock.stop();
+            } else {
+                clock.press(!info.color);
+            }
+        }
+    }
+
+    /// Play `mv` of `info.color` on `board` and add it to the history
+    fn play(board: &mut Board, info: &mut MatchInfos<E>, mv: Move) {
+        let san = board.to_san(info.color, mv);
+        board.do_move(mv);
+        info.color = !info.color;
+        board.update_aggressors(info.color);
+        info.history.push(PlayedMove {
+            mv,
+            san,
+            board: board.clone(),
+        });
+        info.takeback = None;
+        // the opponent of the color that offered a draw moved instead of accepting it
+        if info.draw_offer == Some(info.color) {
+            info.draw_offer = None;
+        }
+        Self::update_result(board, info);
+        Self::press_clock(info);
     }
 
-    fn spawn_decision_maker(&self, id: u32, color: Color, board: &Board) {
+    /// Let the computer answer `last_move`, which lead to `board`
+    fn spawn_decision_maker(
+        &self,
+        id: u32,
+        color: Color,
+        board: &Board,
+        last_move: Move,
+        ponder: Option<Ponder>,
+        config: Config,
+    ) {
         let boards = self.boards.clone();
         let infos = self.infos.clone();
+        let ponders = self.ponders.clone();
+        let storage = self.storage.clone();
         let board = board.clone();
+        let stop = match &ponder {
+            Some(ponder) if ponder.expected == last_move => ponder.stop.clone(),
+            _ => Arc::new(AtomicBool::new(false)),
+        };
+        self.searches.lock().unwrap().insert(id, stop.clone());
+        let observer: Observer = {
+            let infos = self.infos.clone();
+            let stop = stop.clone();
+            Arc::new(move |progress: &SearchProgress| {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(i) = infos.write().unwrap().get_mut(&id) {
+                    i.thinking = Some(progress.clone());
+                }
+            })
+        };
         let _handle = std::thread::spawn(move || {
-            if let Some(mv) =
-                crate::decision::decide(&board, color, crate::decision::DEFAULT_CONFIG)
-            {
+            let result = match ponder {
+                Some(ponder) if ponder.expected == last_move => ponder.handle.join().unwrap(),
+                ponder => {
+                    if let Some(ponder) = ponder {
+                        ponder.stop();
+                    }
+                    crate::decision::decide_until(
+                        &board,
+                        color,
+                        config.clone(),
+                        Some(observer),
+                        stop.clone(),
+                    )
+                }
+            };
+            if let Some(result) = result {
                 if let (Some(v), Some(i)) = (
-                    boards.write().unwrap().get_mut(id as usize),
-                    infos.write().unwrap().get_mut(id as usize),
+                    boards.write().unwrap().get_mut(&id),
+                    infos.write().unwrap().get_mut(&id),
                 ) {
-                    v.do_move(mv);
-                    i.color = !i.color;
-                    v.update_aggressors(i.color);
+                    // the move was taken back while the computer was thinking
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if Self::update_flag(&storage, id, i) {
+                        i.thinking = None;
+                        return;
+                    }
+                    Self::play(v, i, result.best_move);
+                    Self::store(&storage, id, i);
+                    if config.ponder && i.result.is_none() {
+                        if let Some(ponder) = Self::spawn_ponder(v, color, &result, config) {
+                            ponders.lock().unwrap().insert(id, ponder);
+                        }
+                    }
+                    i.last_search = Some(result);
+                    i.thinking = None;
                 }
             }
         });
     }
 
-    pub fn do_move(&self, id: u32, mv: Move, otherplayerdecide: bool) {
+    /// Search the position after the reply that `result` expects from the opponent
+    fn spawn_ponder(
+        board: &Board,
+        color: Color,
+        result: &SearchResult,
+        config: Config,
+    ) -> Option<Ponder> {
+        let expected = *result.pv.get(1)?;
+        let mut board = board.clone();
+        board.do_move(expected);
+        board.update_aggressors(color);
+        let stop = Arc::new(AtomicBool::new(false));
+        let ponder_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            crate::decision::decide_until(&board, color, config, None, ponder_stop)
+        });
+        Some(Ponder {
+            expected,
+            stop,
+            handle,
+        })
+    }
+
+    /// Play `mv` and let the computer answer it with `computer` unless the opponent is human
+    pub fn do_move(&self, id: u32, mv: Move, computer: Option<Config>) {
         if let (Some(v), Some(i)) = (
-            self.boards.write().unwrap().get_mut(id as usize),
-            self.infos.write().unwrap().get_mut(id as usize),
+            self.boards.write().unwrap().get_mut(&id),
+            self.infos.write().unwrap().get_mut(&id),
         ) {
-            v.do_move(mv);
+            let ponder = self.ponders.lock().unwrap().remove(&id);
+            if Self::update_flag(&self.storage, id, i) {
+                if let Some(ponder) = ponder {
+                    ponder.stop();
+                }
+                return;
+            }
+            Self::play(v, i, mv);
+            Self::store(&self.storage, id, i);
+            if let (None, Some(mut config)) = (i.result, computer) {
+                if let Some(clock) = &i.clock {
+                    clock.apply(i.color, &mut config);
+                }
+                return self.spawn_decision_maker(id, i.color, &*v, mv, ponder, config);
+            }
+            if let Some(ponder) = ponder {
+                ponder.stop();
+            }
+        }
+    }
+
+    /// Stop the computer from thinking and pondering
+    fn stop_searches(&self, id: u32) {
+        if let Some(ponder) = self.ponders.lock().unwrap().remove(&id) {
+            ponder.stop();
+        }
+        if let Some(stop) = self.searches.lock().unwrap().remove(&id) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// End the match with `result` before it is decided on the board
+    fn end_match(&self, id: u32, info: &mut MatchInfos<E>, result: MatchResult) {
+        self.stop_searches(id);
+        info.result = Some(result);
+        info.thinking = None;
+        info.takeback = None;
+        info.draw_offer = None;
+        if let Some(clock) = &mut info.clock {
+            clock.stop();
+        }
+        Self::store(&self.storage, id, info);
+    }
+
+    pub fn resign(&self, id: u32, color: Color) -> Result<(), DrawError> {
+        let mut infos = self.infos.write().unwrap();
+        let i = infos.get_mut(&id).ok_or(DrawError::UnknownMatch)?;
+        if Self::update_flag(&self.storage, id, i) {
+            return Err(DrawError::MatchOver);
+        }
+        self.end_match(id, i, MatchResult::Resignation(!color));
+        Ok(())
+    }
+
+    /// Offer a draw to the opponent of `color`. The `computer` decides on it from its search
+    /// score in the background and takes the offer back if it declines.
+    pub fn offer_draw(
+        &self,
+        id: u32,
+        color: Color,
+        computer: Option<Config>,
+    ) -> Result<(), DrawError> {
+        let (board, to_move, moves) = {
+            let boards = self.boards.read().unwrap();
+            let mut infos = self.infos.write().unwrap();
+            let (v, i) = match (boards.get(&id), infos.get_mut(&id)) {
+                (Some(v), Some(i)) => (v, i),
+                _ => return Err(DrawError::UnknownMatch),
+            };
+            if Self::update_flag(&self.storage, id, i) {
+                return Err(DrawError::MatchOver);
+            }
+            i.draw_offer = Some(color);
+            (v.clone(), i.color, i.history.len())
+        };
+        let mut config = match computer {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        // the plain score of the search, without the choices that only make the play varied
+        config.book = None;
+        config.tablebase = None;
+        config.random_margin = 0;
+        config.seed = None;
+        config.multi_pv = 1;
+        let margin = config.eval.pawn_value / DRAW_ACCEPT_DIVISOR;
+        config.move_time = Some(
+            config
+                .move_time
+                .map_or(DRAW_OFFER_TIME, |t| t.min(DRAW_OFFER_TIME)),
+        );
+        let registry = self.shared();
+        let _handle = std::thread::spawn(move || {
+            // the score of the computer, whether it is to move or not
+            let score = match crate::decision::decide(&board, to_move, config, None) {
+                Some(result) if to_move == color => -result.score,
+                Some(result) => result.score,
+                None => Score::max(),
+            };
+            let mut infos = registry.infos.write().unwrap();
+            let i = match infos.get_mut(&id) {
+                Some(i) => i,
+                None => return,
+            };
+            // the computer moved or the match ended in the meantime
+            if i.draw_offer != Some(color)
+                || i.history.len() != moves
+                || Self::update_flag(&registry.storage, id, i)
+            {
+                return;
+            }
+            if score > Score::Value(margin) {
+                i.draw_offer = None;
+            } else {
+                registry.end_match(id, i, MatchResult::DrawByAgreement);
+            }
+        });
+        Ok(())
+    }
+
+    /// Accept or decline the draw that the opponent of `color` offered
+    pub fn answer_draw(&self, id: u32, color: Color, accept: bool) -> Result<(), DrawError> {
+        let mut infos = self.infos.write().unwrap();
+        let i = infos.get_mut(&id).ok_or(DrawError::UnknownMatch)?;
+        if Self::update_flag(&self.storage, id, i) {
+            return Err(DrawError::MatchOver);
+        }
+        match i.draw_offer {
+            Some(offer) if offer != color => (),
+            _ => return Err(DrawError::NoOffer),
+        }
+        i.draw_offer = None;
+        if accept {
+            self.end_match(id, i, MatchResult::DrawByAgreement);
+        }
+        Ok(())
+    }
+
+    /// Take back the last `plies` half moves, stopping the computer if it is thinking.
+    /// The caller decides who may do so and lets the computer move if it is to move again.
+    pub fn undo(&self, id: u32, plies: usize) -> Result<(), UndoError> {
+        let mut boards = self.boards.write().unwrap();
+        let mut infos = self.infos.write().unwrap();
+        let (v, i) = match (boards.get_mut(&id), infos.get_mut(&id)) {
+            (Some(v), Some(i)) => (v, i),
+            _ => return Err(UndoError::UnknownMatch),
+        };
+        if Self::update_flag(&self.storage, id, i) {
+            return Err(UndoError::MatchOver);
+        }
+        if plies == 0 || plies > i.history.len() {
+            return Err(UndoError::NotEnoughMoves);
+        }
+        self.stop_searches(id);
+        i.history.truncate(i.history.len() - plies);
+        *v = i
+            .history
+            .last()
+            .map_or_else(|| i.start.clone(), |m| m.board.clone());
+        if plies % 2 == 1 {
             i.color = !i.color;
-            v.update_aggressors(i.color);
-            let mut moves = LongMoveList::new();
-            v.enumerate_all_moves_by(i.color, &mut moves);
-            if moves.is_empty() {
-                i.result = Some(if v.get_king(i.color).aggressors.is_empty() {
-                    MatchResult::Stalemate
-                } else if let Color::White = i.color {
-                    MatchResult::BlackWins
-                } else {
-                    MatchResult::WhiteWins
-                })
-            } else if otherplayerdecide {
-                self.spawn_decision_maker(id, i.color, &*v);
+        }
+        i.takeback = None;
+        i.draw_offer = None;
+        i.last_search = None;
+        i.thinking = None;
+        if let Some(clock) = &mut i.clock {
+            clock.restart(i.color);
+        }
+        Self::store(&self.storage, id, i);
+        Ok(())
+    }
+
+    /// Ask the opponent of `color` to approve taking back the last `plies` half moves
+    pub fn request_undo(&self, id: u32, color: Color, plies: usize) -> Result<(), UndoError> {
+        let mut infos = self.infos.write().unwrap();
+        let i = infos.get_mut(&id).ok_or(UndoError::UnknownMatch)?;
+        if Self::update_flag(&self.storage, id, i) {
+            return Err(UndoError::MatchOver);
+        }
+        if plies == 0 || plies > i.history.len() {
+            return Err(UndoError::NotEnoughMoves);
+        }
+        i.takeback = Some(TakebackRequest { color, plies });
+        Ok(())
+    }
+
+    /// Approve or decline the takeback that the opponent of `color` asked for
+    pub fn answer_undo(&self, id: u32, color: Color, approve: bool) -> Result<(), UndoError> {
+        let request = {
+            let mut infos = self.infos.write().unwrap();
+            let i = infos.get_mut(&id).ok_or(UndoError::UnknownMatch)?;
+            match i.takeback {
+                Some(request) if request.color != color => {
+                    i.takeback = None;
+                    request
+                }
+                _ => return Err(UndoError::NoRequest),
             }
+        };
+        if approve {
+            self.undo(id, request.plies)
+        } else {
+            Ok(())
         }
     }
 
+    /// The infos of the match, which is lost on time once the flag of the color to move falls
     pub fn get_info(&self, id: u32) -> Option<MatchInfos<E>> {
-        self.infos.read().unwrap().get(id as usize).cloned()
+        let mut infos = self.infos.write().unwrap();
+        let info = infos.get_mut(&id)?;
+        Self::update_flag(&self.storage, id, info);
+        Some(info.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Bonus;
+    use crate::storage::JsonFileStorage;
+
+    fn play(registry: &MatchRegistry<u32>, id: u32, moves: &[&str]) {
+        for san in moves {
+            let info = registry.get_info(id).unwrap();
+            let board = registry.get_board(id).unwrap();
+            let mv = board.parse_san(info.color, san).unwrap();
+            registry.do_move(id, mv, None);
+        }
+    }
+
+    #[test]
+    fn draw_offer() {
+        let registry = MatchRegistry::<u32>::new();
+        let config = Config {
+            depth: 2,
+            ..DEFAULT_CONFIG
+        };
+        // the computer playing black accepts when it is lost and declines when it is winning
+        for &(fen, accept) in &[
+            ("4k3/8/8/8/8/8/8/QQQ1K3 w - - 0 1", true),
+            ("qqq1k3/8/8/8/8/8/8/4K3 w - - 0 1", false),
+        ] {
+            let (board, _) = Board::from_fen(fen).unwrap();
+            let id = registry.create_match_from(board, None, 0);
+            registry
+                .offer_draw(id, Color::White, Some(config.clone()))
+                .unwrap();
+            for _ in 0..100 {
+                let info = registry.get_info(id).unwrap();
+                if info.draw_offer.is_none() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            let info = registry.get_info(id).unwrap();
+            assert!(info.draw_offer.is_none());
+            assert_eq!(
+                matches!(info.result, Some(MatchResult::DrawByAgreement)),
+                accept,
+                "{}",
+                fen
+            );
+        }
+        // a human opponent has to answer the offer
+        let id = registry.create_match(0);
+        registry.offer_draw(id, Color::White, None).unwrap();
+        assert!(registry.answer_draw(id, Color::White, true).is_err());
+        registry.answer_draw(id, Color::Black, true).unwrap();
+        assert!(matches!(
+            registry.get_info(id).unwrap().result,
+            Some(MatchResult::DrawByAgreement)
+        ));
+    }
+
+    #[test]
+    fn store_and_restore() {
+        let dir = std::env::temp_dir().join(format!("scha256-matches-{}", std::process::id()));
+        let storage = Arc::new(JsonFileStorage::open(&dir).unwrap());
+        let failed = Arc::new(Mutex::new(vec![]));
+        let on_error: StorageErrorHandler = {
+            let failed = failed.clone();
+            Arc::new(move |id, _| failed.lock().unwrap().push(id))
+        };
+        let registry = MatchRegistry::with_storage(storage.clone(), on_error.clone()).unwrap();
+        let control = TimeControl {
+            base: Duration::from_secs(300),
+            bonus: Bonus::Increment(Duration::from_secs(2)),
+        };
+        let (mut board, _) =
+            Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        board.set_variant(Variant::Crazyhouse);
+        let id = registry.create_match_from(board, Some(control), 7);
+        play(
+            &registry,
+            id,
+            &["e4", "d5", "exd5", "Qxd5", "Nc3", "Qa5", "P@d5"],
+        );
+        let ended = registry.create_match(8);
+        play(&registry, ended, &["f3", "e5", "g4", "Qh4"]);
+        // a broken record and gaps in the ids must not stop the others from loading
+        std::fs::write(dir.join("4.json"), "{").unwrap();
+        let json = std::fs::read_to_string(dir.join("0.json")).unwrap();
+        std::fs::write(dir.join(format!("{}.json", u32::MAX)), &json).unwrap();
+        let record: MatchRecord<u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            record.moves,
+            ["e4", "d5", "exd5", "Qxd5", "Nc3", "Qa5", "P@d5"]
+        );
+        assert_eq!(record.extra, 7);
+
+        let restored = MatchRegistry::<u32>::with_storage(storage, on_error).unwrap();
+        assert_eq!(*failed.lock().unwrap(), [4]);
+        assert_eq!(restored.get_info(u32::MAX).unwrap().history.len(), 7);
+        assert!(restored.get_info(3).is_none());
+        let (before, after) = (
+            registry.get_info(id).unwrap(),
+            restored.get_info(id).unwrap(),
+        );
+        assert_eq!(after.extra, 7);
+        assert_eq!(after.color, Color::Black);
+        assert_eq!(after.start.variant(), Variant::Crazyhouse);
+        let sans =
+            |i: &MatchInfos<u32>| i.history.iter().map(|m| m.san.clone()).collect::<Vec<_>>();
+        assert_eq!(sans(&before), sans(&after));
+        assert_eq!(
+            registry.get_board(id).unwrap().to_fen(Color::Black),
+            restored.get_board(id).unwrap().to_fen(Color::Black)
+        );
+        let clock = after.clock.unwrap();
+        assert_eq!(clock.control, control);
+        assert!(clock.is_running(Color::Black));
+        assert!(clock.remaining(Color::White) > Duration::from_secs(300));
+        assert!(matches!(
+            restored.get_info(ended).unwrap().result,
+            Some(MatchResult::BlackWins)
+        ));
+        // the computer playing black continues the restored match
+        restored.resume_computers(|&extra, color| match (extra, color) {
+            (7, Color::Black) => Some(Config {
+                depth: 2,
+                ..DEFAULT_CONFIG
+            }),
+            _ => None,
+        });
+        for _ in 0..100 {
+            if restored.get_info(id).unwrap().color == Color::White {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(restored.get_info(id).unwrap().history.len(), 8);
+        // the free ids are given out from the lowest
+        for id in 2..6 {
+            assert_eq!(restored.create_match(9), id);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
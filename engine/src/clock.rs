@@ -0,0 +1,260 @@
+use crate::board::Color;
+use crate::decision::Config;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// The computer plans its time as if this many moves were left
+const MOVES_TO_GO: u32 = 30;
+
+/// The time a player gains for every move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bonus {
+    /// Fischer increment, added to the clock after the move
+    Increment(Duration),
+    /// The clock only starts running once this delay has passed
+    Delay(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub bonus: Bonus,
+}
+
+/// The base time in minutes and the bonus in seconds e.g. `5+3` or `5 delay 3`
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let minutes = self.base.as_secs_f64() / 60.0;
+        match self.bonus {
+            Bonus::Increment(t) => write!(f, "{}+{}", minutes, t.as_secs_f64()),
+            Bonus::Delay(t) => write!(f, "{} delay {}", minutes, t.as_secs_f64()),
+        }
+    }
+}
+
+/// The chess clock of a match, which starts with the first move
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    /// The time of white and black when their last move ended
+    remaining: [Duration; 2],
+    /// The color whose time is running and since when
+    running: Option<(Color, Instant)>,
+}
+
+const fn index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+impl Clock {
+    pub const fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            remaining: [control.base; 2],
+            running: None,
+        }
+    }
+
+    /// Continue a clock with the `times` of white and black, whose time of `running` runs
+    pub fn resume(control: TimeControl, times: [Duration; 2], running: Option<Color>) -> Self {
+        Self {
+            control,
+            remaining: times,
+            running: running.map(|color| (color, Instant::now())),
+        }
+    }
+
+    /// The time left on the clocks of white and black at this moment
+    pub fn times(&self) -> [Duration; 2] {
+        [self.remaining(Color::White), self.remaining(Color::Black)]
+    }
+
+    pub fn running_color(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    pub fn is_running(&self, color: Color) -> bool {
+        matches!(self.running, Some((c, _)) if c == color)
+    }
+
+    /// The time of the delay that `color` has not used yet
+    pub fn remaining_delay(&self, color: Color) -> Duration {
+        match (self.control.bonus, self.running) {
+            (Bonus::Delay(delay), Some((c, since))) if c == color => {
+                delay.checked_sub(since.elapsed()).unwrap_or_default()
+            }
+            (Bonus::Delay(delay), _) => delay,
+            (Bonus::Increment(_), _) => Duration::from_secs(0),
+        }
+    }
+
+    /// The time left on the clock of `color` at this moment
+    pub fn remaining(&self, color: Color) -> Duration {
+        let stored = self.remaining[index(color)];
+        match self.running {
+            Some((c, since)) if c == color => {
+                let used = match self.control.bonus {
+                    Bonus::Delay(delay) => since.elapsed().checked_sub(delay).unwrap_or_default(),
+                    Bonus::Increment(_) => since.elapsed(),
+                };
+                stored.checked_sub(used).unwrap_or_default()
+            }
+            _ => stored,
+        }
+    }
+
+    pub fn has_flag_fallen(&self, color: Color) -> bool {
+        self.remaining(color) == Duration::from_secs(0)
+    }
+
+    /// End the move of `color`, add its increment and start the time of the opponent
+    pub(crate) fn press(&mut self, color: Color) {
+        let increment = match self.control.bonus {
+            Bonus::Increment(t) => t,
+            Bonus::Delay(_) => Duration::from_secs(0),
+        };
+        self.remaining[index(color)] = self.remaining(color) + increment;
+        self.running = Some((!color, Instant::now()));
+    }
+
+    /// Stop the running time, e.g. at the end of the match
+    pub(crate) fn stop(&mut self) {
+        if let Some((color, _)) = self.running {
+            self.remaining[index(color)] = self.remaining(color);
+            self.running = None;
+        }
+    }
+
+    /// Keep the times but let the one of `color` run from now on, e.g. after a takeback
+    pub(crate) fn restart(&mut self, color: Color) {
+        self.stop();
+        self.running = Some((color, Instant::now()));
+    }
+
+    /// Limit the time of the computer playing `color` to a share of its remaining time and
+    /// most of its bonus, but never more than half of the clock
+    pub fn apply(&self, color: Color, config: &mut Config) {
+        let remaining = self.remaining(color);
+        let bonus = match self.control.bonus {
+            Bonus::Increment(t) | Bonus::Delay(t) => t,
+        };
+        let budget = (remaining / MOVES_TO_GO + bonus * 3 / 4).min(remaining / 2);
+        config.move_time = Some(config.move_time.map_or(budget, |t| t.min(budget)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::DEFAULT_CONFIG;
+
+    const fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn control(base: u64, bonus: Bonus) -> TimeControl {
+        TimeControl {
+            base: secs(base),
+            bonus,
+        }
+    }
+
+    /// Let the time of `color` run since `elapsed` ago
+    fn run_since(clock: &mut Clock, color: Color, elapsed: Duration) {
+        clock.running = Some((color, Instant::now() - elapsed));
+    }
+
+    fn assert_about(actual: Duration, expected: Duration) {
+        assert!(
+            actual <= expected && expected - actual < Duration::from_millis(500),
+            "{:?} is not about {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn increment() {
+        let mut clock = Clock::new(control(300, Bonus::Increment(secs(2))));
+        assert_eq!(clock.times(), [secs(300); 2]);
+        assert_eq!(clock.running_color(), None);
+        clock.press(Color::White);
+        assert_eq!(clock.remaining(Color::White), secs(302));
+        assert!(clock.is_running(Color::Black));
+        run_since(&mut clock, Color::Black, secs(10));
+        assert_about(clock.remaining(Color::Black), secs(290));
+        assert_eq!(clock.remaining_delay(Color::Black), secs(0));
+        clock.press(Color::Black);
+        assert_about(clock.remaining(Color::Black), secs(292));
+        assert!(clock.is_running(Color::White));
+        clock.stop();
+        assert_eq!(clock.running_color(), None);
+        let times = clock.times();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(clock.times(), times);
+        clock.restart(Color::Black);
+        assert!(clock.is_running(Color::Black));
+    }
+
+    #[test]
+    fn delay() {
+        let mut clock = Clock::new(control(60, Bonus::Delay(secs(5))));
+        assert_eq!(clock.remaining_delay(Color::White), secs(5));
+        run_since(&mut clock, Color::White, secs(3));
+        assert_eq!(clock.remaining(Color::White), secs(60));
+        assert_about(clock.remaining_delay(Color::White), secs(2));
+        run_since(&mut clock, Color::White, secs(8));
+        assert_about(clock.remaining(Color::White), secs(57));
+        assert_eq!(clock.remaining_delay(Color::White), secs(0));
+        // the delay is not added to the clock
+        clock.press(Color::White);
+        assert_about(clock.remaining(Color::White), secs(57));
+        assert_eq!(clock.remaining_delay(Color::White), secs(5));
+    }
+
+    #[test]
+    fn flag() {
+        let mut clock = Clock::resume(
+            control(60, Bonus::Increment(secs(1))),
+            [secs(5), secs(60)],
+            Some(Color::White),
+        );
+        assert!(!clock.has_flag_fallen(Color::White));
+        run_since(&mut clock, Color::White, secs(6));
+        assert!(clock.has_flag_fallen(Color::White));
+        assert_eq!(clock.remaining(Color::White), secs(0));
+        assert!(!clock.has_flag_fallen(Color::Black));
+    }
+
+    #[test]
+    fn apply() {
+        let budget = |remaining, increment, move_time| {
+            let clock = Clock::resume(
+                control(300, Bonus::Increment(secs(increment))),
+                [secs(remaining); 2],
+                None,
+            );
+            let mut config = DEFAULT_CONFIG;
+            config.move_time = move_time;
+            clock.apply(Color::White, &mut config);
+            config.move_time.unwrap()
+        };
+        // a thirtieth of the time and three quarters of the increment
+        assert_eq!(budget(300, 2, None), Duration::from_millis(11_500));
+        assert_eq!(budget(300, 2, Some(secs(5))), secs(5));
+        // but never more than half of the clock
+        assert_eq!(budget(10, 30, None), secs(5));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(control(300, Bonus::Increment(secs(3))).to_string(), "5+3");
+        assert_eq!(
+            control(90, Bonus::Delay(secs(2))).to_string(),
+            "1.5 delay 2"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::board::{Board, Color, Coord, Field, Piece};
+use crate::list::List;
+use crate::moves::{Move, MoveType, PromotionType};
+use crate::score::EvalParams;
+
+pub const POCKET_PIECES: [Piece; 5] = [
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Pawn,
+];
+
+/// The captured pieces a player may drop in Crazyhouse
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pocket([u8; 5]);
+
+impl Pocket {
+    pub const fn count(&self, piece: Piece) -> u8 {
+        self.0[piece as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&n| n == 0)
+    }
+
+    pub(crate) fn add(&mut self, piece: Piece) {
+        self.0[piece as usize] += 1;
+    }
+
+    pub(crate) fn take(&mut self, piece: Piece) {
+        self.0[piece as usize] -= 1;
+    }
+}
+
+const fn square_bit(coord: Coord) -> u64 {
+    let (x, y) = coord.as_xy();
+    1 << (y * 8 + x)
+}
+
+impl Board {
+    pub const fn get_pocket(&self, color: Color) -> &Pocket {
+        match color {
+            Color::White => &self.pockets[0],
+            Color::Black => &self.pockets[1],
+        }
+    }
+
+    pub(crate) fn get_pocket_mut(&mut self, color: Color) -> &mut Pocket {
+        match color {
+            Color::White => &mut self.pockets[0],
+            Color::Black => &mut self.pockets[1],
+        }
+    }
+
+    /// Whether the piece on `coord` was a pawn once, it returns to the pocket as a pawn
+    pub const fn is_promoted(&self, coord: Coord) -> bool {
+        self.promoted & square_bit(coord) != 0
+    }
+
+    pub(crate) fn set_promoted(&mut self, coord: Coord, promoted: bool) {
+        if promoted {
+            self.promoted |= square_bit(coord)
+        } else {
+            self.promoted &= !square_bit(coord)
+        }
+    }
+
+    /// Pocket the piece that `mv` of `color` captures or take the dropped one out, which
+    /// has to happen before the move changes the board
+    pub(crate) fn update_pockets(&mut self, mv: Move, color: Color) {
+        let captured = match mv.move_type {
+            MoveType::EnPassant(_) => Some(Piece::Pawn),
+            MoveType::Capture | MoveType::Promote(_, PromotionType::Capture) => {
+                match self.get(mv.end) {
+                    _ if self.is_promoted(mv.end) => Some(Piece::Pawn),
+                    Field::WhitePiece(p) | Field::BlackPiece(p) => Some(*p),
+                    _ => None,
+                }
+            }
+            MoveType::Drop(piece) => {
+                self.get_pocket_mut(color).take(piece);
+                None
+            }
+            _ => None,
+        };
+        if let Some(piece) = captured {
+            self.get_pocket_mut(color).add(piece);
+        }
+        let promoted =
+            self.is_promoted(mv.start) || matches!(mv.move_type, MoveType::Promote(_, _));
+        self.set_promoted(mv.start, false);
+        self.set_promoted(mv.end, promoted);
+    }
+
+    /// Drop the pieces of the pocket of `color` on the empty squares, pawns not on the
+    /// first and last rank
+    pub(crate) fn list_drops<const N: usize>(&self, color: Color, into: &mut List<Move, N>) {
+        let pocket = self.get_pocket(color);
+        for &piece in POCKET_PIECES.iter().filter(|&&p| pocket.count(p) > 0) {
+            let ranks = if piece == Piece::Pawn { 1..7 } else { 0..8 };
+            for y in ranks {
+                for x in 0..8 {
+                    let coord = Coord::from_xy(x, y);
+                    if *self.get(coord) == Field::Empty {
+                        into.append(Move {
+                            start: coord,
+                            end: coord,
+                            move_type: MoveType::Drop(piece),
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    /// The value of the pieces in the pocket of `color`
+    pub(crate) fn get_pocket_material(&self, color: Color, params: &EvalParams) -> i32 {
+        let pocket = self.get_pocket(color);
+        POCKET_PIECES
+            .iter()
+            .map(|&p| pocket.count(p) as i32 * params.value_from_piece(p))
+            .sum()
+    }
+}
@@ -1,82 +1,624 @@
-use crate::board::{Board, Color, Coord, Field, Piece};
+use crate::board::{Board, Color, CommonCoord, Coord, Field, Piece};
+use crate::book::Book;
+use crate::endgame;
 use crate::moves::{LongMoveList, Move, MoveType, PromotionType};
-use crate::score::{self, Score};
+use crate::random::Rng;
+use crate::score::{EvalParams, EvalTerms, EvalTrace, Score, SquareTrace, DEFAULT_EVAL_PARAMS};
+use crate::syzygy::{Tablebase, Wdl};
+use crate::variant::Variant;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub const DEFAULT_CONFIG: Config = Config { depth: 5 };
+pub const DEFAULT_CONFIG: Config = Config {
+    depth: 5,
+    eval: DEFAULT_EVAL_PARAMS,
+    null_move_pruning: true,
+    null_move_reduction: 2,
+    late_move_reductions: true,
+    late_move_threshold: 3,
+    multi_pv: 1,
+    threads: 1,
+    ponder: false,
+    book: None,
+    book_depth: 16,
+    tablebase: None,
+    move_time: None,
+    random_margin: 0,
+    seed: None,
+};
+
+/// The score of a won tablebase position, far above any material balance
+pub const TABLEBASE_WIN: i32 = 100_000;
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub depth: u32,
+    pub eval: EvalParams,
+    /// Skip a turn and prune if the opponent still cannot reach beta
+    pub null_move_pruning: bool,
+    /// The additional depth reduction of the null move search
+    pub null_move_reduction: u32,
+    /// Search late quiet moves with one ply less unless they raise alpha
+    pub late_move_reductions: bool,
+    /// The number of moves that are never reduced
+    pub late_move_threshold: usize,
+    /// The number of ranked root moves returned by `analyze`
+    pub multi_pv: usize,
+    /// The number of threads the root moves are split between
+    pub threads: usize,
+    /// Search the expected reply of the opponent while it is thinking
+    pub ponder: bool,
+    /// Play a weighted random move of this opening book if it knows the position
+    pub book: Option<Arc<Book>>,
+    /// The number of half moves since the start of the game after which the book is ignored
+    pub book_depth: u32,
+    /// Play the best tablebase move at the root and stop searching positions it covers
+    pub tablebase: Option<Arc<Tablebase>>,
+    /// Play the best move of the last completed iteration once this time is up
+    pub move_time: Option<Duration>,
+    /// Play a random one of the best moves, whose score is at most this much below the best
+    pub random_margin: i32,
+    /// Break ties between root moves and make the random choices with a generator seeded by
+    /// this and the position, so a game without `move_time` can be reproduced exactly
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// The score from the perspective of the deciding color
+    pub score: Score,
+    /// The expected continuation starting with `best_move`
+    pub pv: Vec<Move>,
+    pub depth: u32,
+    /// The maximum number of plies that were actually reached
+    pub seldepth: u32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    /// The move was taken from the opening book without searching
+    pub from_book: bool,
+    /// The move was taken from the endgame tablebase without searching
+    pub from_tablebase: bool,
+}
+
+/// A snapshot of a running search
+#[derive(Clone, Debug)]
+pub struct SearchProgress {
+    /// The rank of the searched root move in `analyze` starting at 1
+    pub multipv: usize,
+    pub depth: u32,
+    pub seldepth: u32,
+    pub nodes: u64,
+    pub nps: u64,
+    /// The permille of the hash table that is in use if the search has one
+    pub hashfull: Option<u32>,
+    /// The score of the best line found so far
+    pub score: Score,
+    pub pv: Vec<Move>,
+    pub elapsed: Duration,
+    /// Whether the iteration at `depth` is finished
+    pub completed: bool,
+}
+
+/// Gets called after every iteration and periodically while the search is running
+pub type Observer = Arc<dyn Fn(&SearchProgress) + Send + Sync>;
+
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+/// Check the time for periodic reports every 1024 nodes
+const REPORT_NODE_MASK: u64 = 0x3ff;
+
+/// The number of ranked root moves the random move choice picks from
+const RANDOM_CANDIDATES: usize = 4;
+
+/// Probe the tablebase only with this many plies left, closer to the leaves the evaluation
+/// is cheaper than the probe
+const TABLEBASE_PROBE_DEPTH: u32 = 1;
+
+const KILLER_MOVE_SCORES: [i32; 2] = [800, 700];
+const HISTORY_MOVE_SCORE_LIMIT: i32 = 600;
+
+/// Everything the search learns about the move ordering while it is running
+struct SearchState<'a> {
+    config: &'a Config,
+    /// The color that the search decides a move for
+    color: Color,
+    /// Two quiet moves per ply that recently caused a beta cutoff
+    killers: Vec<[Option<Move>; 2]>,
+    /// Cutoff statistics of quiet moves indexed by piece and destination square
+    history: [[i32; 10 * 12]; 12],
+    /// Whether the search is currently below a null move
+    in_null_move: bool,
+    /// The distance of the current node to the root
+    ply: usize,
+    /// Triangular table with the principal variation of every ply
+    pv: Vec<Vec<Move>>,
+    nodes: u64,
+    seldepth: usize,
+    observer: Option<Observer>,
+    /// Makes the search return the last completed iteration once it is set
+    stop: Arc<AtomicBool>,
+    start: Instant,
+    /// The end of `config.move_time`, which is only enforced after the first iteration
+    deadline: Option<Instant>,
+    timed_out: bool,
+    /// Shuffles the root moves and picks between equally good ones if the search is seeded
+    rng: Option<Rng>,
+    last_report: Duration,
+    /// The rank and depth of the current iteration
+    multipv: usize,
+    depth: u32,
+    /// The score and principal variation of the best root move found so far
+    best: Option<(Score, Vec<Move>)>,
+}
+
+impl<'a> SearchState<'a> {
+    fn new(
+        config: &'a Config,
+        color: Color,
+        observer: Option<Observer>,
+        stop: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            config,
+            color,
+            killers: vec![[None; 2]; config.depth as usize + 1],
+            history: [[0; 10 * 12]; 12],
+            in_null_move: false,
+            ply: 0,
+            pv: vec![vec![]; config.depth as usize + 2],
+            nodes: 0,
+            seldepth: 0,
+            observer,
+            stop,
+            start: Instant::now(),
+            deadline: config.move_time.map(|t| Instant::now() + t),
+            timed_out: false,
+            rng: None,
+            last_report: Duration::from_secs(0),
+            multipv: 1,
+            depth: 0,
+            best: None,
+        }
+    }
+
+    /// The first iteration is always finished, so that there is a move to play
+    fn stopped(&self) -> bool {
+        self.depth > 0 && (self.timed_out || self.stop.load(Ordering::Relaxed))
+    }
+
+    fn check_time(&mut self) {
+        if self.depth > 0 && matches!(self.deadline, Some(d) if Instant::now() >= d) {
+            self.timed_out = true;
+        }
+    }
+
+    fn report(&mut self, completed: bool) {
+        if let (Some(observer), Some((score, pv))) = (&self.observer, &self.best) {
+            let elapsed = self.start.elapsed();
+            observer.as_ref()(&SearchProgress {
+                multipv: self.multipv,
+                depth: self.depth + 1,
+                seldepth: self.seldepth as u32,
+                nodes: self.nodes,
+                nps: (self.nodes as u128 * 1_000_000 / elapsed.as_micros().max(1)) as u64,
+                hashfull: None,
+                score: *score,
+                pv: pv.clone(),
+                elapsed,
+                completed,
+            });
+            self.last_report = elapsed;
+        }
+    }
+
+    fn report_periodically(&mut self) {
+        if self.observer.is_some() && self.start.elapsed() - self.last_report >= REPORT_INTERVAL {
+            self.report(false)
+        }
+    }
+
+    fn enter_child(&mut self) {
+        self.ply += 1;
+        self.nodes += 1;
+        if self.nodes & REPORT_NODE_MASK == 0 {
+            self.check_time();
+            self.report_periodically();
+        }
+        self.seldepth = self.seldepth.max(self.ply);
+        self.pv[self.ply].clear();
+    }
+
+    fn leave_child(&mut self) {
+        self.ply -= 1;
+    }
+
+    /// The move raised alpha, so it continues with the principal variation of the child
+    fn update_pv(&mut self, mv: Move) {
+        let (pv, child_pv) = self.pv.split_at_mut(self.ply + 1);
+        let pv = &mut pv[self.ply];
+        pv.clear();
+        pv.push(mv);
+        pv.extend_from_slice(&child_pv[0]);
+    }
+
+    fn history_index(field: Field) -> Option<usize> {
+        match field {
+            Field::WhiteKing => Some(0),
+            Field::BlackKing => Some(1),
+            Field::WhitePiece(p) => Some(2 + p as usize),
+            Field::BlackPiece(p) => Some(7 + p as usize),
+            _ => None,
+        }
+    }
+
+    fn get_quiet_move_score(&self, board: &Board, mv: &Move, ply: usize) -> i32 {
+        if let Some(i) = self.killers[ply].iter().position(|k| k == &Some(*mv)) {
+            return KILLER_MOVE_SCORES[i];
+        }
+        Self::history_index(*board.get(mv.start)).map_or(0, |i| {
+            self.history[i][mv.end.raw() as usize].min(HISTORY_MOVE_SCORE_LIMIT)
+        })
+    }
+
+    /// Remember a quiet move that caused a beta cutoff `d` plies above the horizon
+    fn store_cutoff(&mut self, board: &Board, mv: Move, d: u32) {
+        if !is_quiet(&mv) {
+            return;
+        }
+        let killers = &mut self.killers[self.ply];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+        if let Some(i) = Self::history_index(*board.get(mv.start)) {
+            self.history[i][mv.end.raw() as usize] += (d * d) as i32 + 1;
+        }
+    }
+
+    fn null_move_allowed(&self, board: &Board, d: u32, color: Color) -> bool {
+        self.config.null_move_pruning
+            && !self.in_null_move
+            && self.ply > 0
+            && d > self.config.null_move_reduction
+            && board.get_king(color).aggressors.is_empty()
+            && has_non_pawn_material(board, color)
+            // forced captures make zugzwang the rule rather than the exception
+            && board.variant() != Variant::Antichess
+    }
+
+    fn get_late_move_reduction(
+        &self,
+        board: &Board,
+        mv: &Move,
+        n: usize,
+        d: u32,
+        color: Color,
+    ) -> u32 {
+        if self.config.late_move_reductions
+            && self.ply > 0
+            && n >= self.config.late_move_threshold
+            && d >= 2
+            && is_quiet(mv)
+            && !self.killers[self.ply].contains(&Some(*mv))
+            && board.get_king(color).aggressors.is_empty()
+        {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Zugzwang positions are likely if a side only has its king and pawns left
+fn has_non_pawn_material(board: &Board, color: Color) -> bool {
+    (0..64).any(|i| {
+        matches!(
+            (color, board.get(Coord::from_xy(i % 8, i / 8))),
+            (Color::White, Field::WhitePiece(p)) | (Color::Black, Field::BlackPiece(p))
+                if *p != Piece::Pawn
+        )
+    })
+}
+
+fn is_quiet(mv: &Move) -> bool {
+    matches!(
+        mv.move_type,
+        MoveType::Regular | MoveType::RegularPawnDoubleForward
+    )
+}
+
+pub fn decide(
+    board: &Board,
+    color: Color,
+    config: Config,
+    observer: Option<Observer>,
+) -> Option<SearchResult> {
+    decide_until(
+        board,
+        color,
+        config,
+        observer,
+        Arc::new(AtomicBool::new(false)),
+    )
+}
+
+/// Like `decide`, but return the result of the last completed iteration once `stop` is set
+pub fn decide_until(
+    board: &Board,
+    color: Color,
+    config: Config,
+    observer: Option<Observer>,
+    stop: Arc<AtomicBool>,
+) -> Option<SearchResult> {
+    let rng = config
+        .seed
+        .map(|seed| Rng::new(seed ^ board.polyglot_key(color)));
+    let mut book_rng = rng.clone().unwrap_or_else(Rng::from_time);
+    if let Some(result) = book_move(board, color, &config, &mut book_rng) {
+        return Some(result);
+    }
+    if let Some(result) = tablebase_move(board, color, &config) {
+        return Some(result);
+    }
+    let mut state = SearchState::new(&config, color, observer, stop);
+    state.rng = rng;
+    if config.random_margin > 0 || state.rng.is_some() {
+        return random_move(board, color, &mut state);
+    }
+    let mut moves = get_root_moves(board, color, &mut state);
+    search_root(board, &mut moves, color, &mut state)
+}
+
+/// Rank the best root moves and pick one within `config.random_margin` of the best, so a
+/// seeded search also varies between moves with the same score
+fn random_move(board: &Board, color: Color, state: &mut SearchState) -> Option<SearchResult> {
+    let margin = state.config.random_margin;
+    let results = rank_moves(board, color, RANDOM_CANDIDATES, Some(margin), state);
+    let best = results.first()?;
+    // a move searched less deeply because of the time or a stop does not compare
+    let candidates: Vec<_> = results
+        .iter()
+        .filter(|r| r.depth == best.depth && r.score >= best.score + -margin)
+        .collect();
+    let rng = state.rng.get_or_insert_with(Rng::from_time);
+    let chosen = rng.below(candidates.len() as u64) as usize;
+    Some(candidates[chosen].clone())
+}
+
+fn book_move(board: &Board, color: Color, config: &Config, rng: &mut Rng) -> Option<SearchResult> {
+    let book = config
+        .book
+        .as_ref()
+        .filter(|_| board.ply() < config.book_depth && board.variant() == Variant::Standard)?;
+    let start = Instant::now();
+    let best_move = book.choose(board, color, rng)?;
+    Some(SearchResult {
+        best_move,
+        score: Score::Value(0),
+        pv: vec![best_move],
+        depth: 0,
+        seldepth: 0,
+        nodes: 0,
+        elapsed: start.elapsed(),
+        from_book: true,
+        from_tablebase: false,
+    })
+}
+
+/// The score of a tablebase result, a win is preferred the fewer plies it takes to reach
+fn tablebase_score(wdl: Wdl, ply: usize) -> Score {
+    // without a fifty-move rule cursed wins are still wins
+    match wdl.signum() {
+        0 => Score::Value(0),
+        s => Score::Value(s * (TABLEBASE_WIN - ply as i32)),
+    }
 }
 
-pub fn decide(board: &Board, color: Color, config: Config) -> Option<Move> {
-    let moves = get_sorted_moves(board, color);
-    max_stage(
+fn tablebase_move(board: &Board, color: Color, config: &Config) -> Option<SearchResult> {
+    let tablebase = config.tablebase.as_ref()?;
+    let start = Instant::now();
+    let (best_move, wdl) = tablebase.best_move(board, color)?;
+    Some(SearchResult {
+        best_move,
+        score: tablebase_score(wdl, 0),
+        pv: vec![best_move],
+        depth: 0,
+        seldepth: 0,
+        nodes: 0,
+        elapsed: start.elapsed(),
+        from_book: false,
+        from_tablebase: true,
+    })
+}
+
+/// Rank the best `config.multi_pv` root moves, each searched with a full window
+pub fn analyze(
+    board: &Board,
+    color: Color,
+    config: Config,
+    observer: Option<Observer>,
+) -> Vec<SearchResult> {
+    analyze_until(
         board,
-        &moves,
-        config.depth,
-        [Score::min(), Score::max()],
         color,
+        config,
+        observer,
+        Arc::new(AtomicBool::new(false)),
     )
-    .map(|(m, _)| m)
 }
 
-fn get_move_score(board: &Board, mv: &Move) -> i32 {
+/// Like `analyze`, but rank no further moves once `stop` is set
+pub fn analyze_until(
+    board: &Board,
+    color: Color,
+    config: Config,
+    observer: Option<Observer>,
+    stop: Arc<AtomicBool>,
+) -> Vec<SearchResult> {
+    let mut state = SearchState::new(&config, color, observer, stop);
+    rank_moves(board, color, config.multi_pv, None, &mut state)
+}
+
+/// Search the best `count` root moves one after another, each with a full window, and stop
+/// after the first move that scores more than `margin` below the best
+fn rank_moves(
+    board: &Board,
+    color: Color,
+    count: usize,
+    margin: Option<i32>,
+    state: &mut SearchState,
+) -> Vec<SearchResult> {
+    let mut moves = get_root_moves(board, color, state);
+    let mut results: Vec<SearchResult> = vec![];
+    for multipv in 1..=count.max(1) {
+        if multipv > 1 && state.stop.load(Ordering::Relaxed) {
+            break;
+        }
+        state.multipv = multipv;
+        match search_root(board, &mut moves, color, state) {
+            Some(result) => {
+                moves.filter(0, |m| *m != result.best_move);
+                let worse = match (margin, results.first()) {
+                    (Some(margin), Some(best)) => result.score < best.score + -margin,
+                    _ => false,
+                };
+                results.push(result);
+                if worse {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    results
+}
+
+/// Search the root moves with iterative deepening up to `config.depth`
+fn search_root(
+    board: &Board,
+    moves: &mut LongMoveList,
+    color: Color,
+    state: &mut SearchState,
+) -> Option<SearchResult> {
+    let mut result = None;
+    state.best = None;
+    state.seldepth = 0;
+    state.timed_out = false;
+    for depth in 0..=state.config.depth {
+        state.depth = depth;
+        state.pv[0].clear();
+        let searched = if state.config.threads > 1 {
+            split_root_moves(board, moves, depth, color, state)
+        } else {
+            negamax(
+                board,
+                moves,
+                depth,
+                [Score::min(), Score::max()],
+                color,
+                state,
+            )
+        };
+        if state.stopped() {
+            break;
+        }
+        let (best_move, score) = searched?;
+        let mut pv = core::mem::take(&mut state.pv[0]);
+        if pv.first() != Some(&best_move) {
+            pv = vec![best_move];
+        }
+        state.best = Some((score, pv.clone()));
+        state.report(true);
+        // search the best move of this iteration first in the next one
+        if let Some(i) = moves.slice().iter().position(|&m| m == best_move) {
+            moves.slice_mut()[..=i].rotate_right(1);
+        }
+        result = Some(SearchResult {
+            best_move,
+            score,
+            pv,
+            depth: depth + 1,
+            seldepth: state.seldepth as u32,
+            nodes: state.nodes,
+            elapsed: state.start.elapsed(),
+            from_book: false,
+            from_tablebase: false,
+        });
+        // do not start another iteration once the time is up
+        state.check_time();
+        if state.stopped() {
+            break;
+        }
+    }
+    result
+}
+
+fn get_move_score(board: &Board, mv: &Move, params: &EvalParams) -> i32 {
     let s = |f| match board.get(f) {
-        Field::BlackPiece(p) | Field::WhitePiece(p) => Score::value_from_piece(*p),
+        Field::BlackPiece(p) | Field::WhitePiece(p) => params.value_from_piece(*p),
         _ => 0,
     };
     match mv.move_type {
-        MoveType::Capture => s(mv.end) - (s(mv.start) >> 2),
-        MoveType::Castle(_) => score::CASTLING_MOVE_SCORE,
+        MoveType::Capture => s(mv.end) - (s(mv.start) >> params.capture_attacker_shift),
+        MoveType::Castle(_) => params.castling_move_score,
         MoveType::Promote(p, t) => {
-            Score::value_from_piece(p)
+            params.value_from_piece(p)
                 + match t {
                     PromotionType::Regular => 0,
                     PromotionType::Capture => s(mv.end),
                 }
         }
-        MoveType::EnPassant(_) => Score::value_from_piece(Piece::Pawn),
+        MoveType::EnPassant(_) => params.value_from_piece(Piece::Pawn),
         _ => 0,
     }
 }
 
-fn get_white_board_score(board: &Board) -> i32 {
+/// The evaluation terms caused by a single square for white and for black
+fn get_square_terms(board: &Board, coord: Coord, params: &EvalParams) -> [EvalTerms; 2] {
+    let (mut white, mut black) = (EvalTerms::default(), EvalTerms::default());
+    let f = board.get(coord);
+    match f {
+        Field::WhitePiece(p) => white.material = params.value_from_piece(*p),
+        Field::BlackPiece(p) => black.material = params.value_from_piece(*p),
+        _ => (),
+    };
+    let bounty = params.threat_bounty(*f);
+    for &t in board.threat_mask.get(coord).slice() {
+        match board.get(t) {
+            Field::WhitePiece(_) | Field::WhiteKing => white.threat_bounty += bounty,
+            Field::BlackPiece(_) | Field::BlackKing => black.threat_bounty += bounty,
+            _ => (),
+        }
+    }
+    let (x, _y) = coord.as_xy();
+    white.centralization = params.central_piece_awardening[x as usize];
+    if x == 0 || x == 7 {
+        match *f {
+            Field::WhitePiece(p) => white.border_penalty = params.border_penalty(p),
+            Field::BlackPiece(p) => black.border_penalty = params.border_penalty(p),
+            _ => (),
+        }
+    }
+    [white, black]
+}
+
+pub fn get_white_board_score(board: &Board, params: &EvalParams) -> i32 {
+    let variant = board.variant();
+    if variant == Variant::Standard {
+        if let Some(score) = endgame::get_white_score(board, params) {
+            return score;
+        }
+    }
     let mut n = 21;
-    let mut s = 0;
+    let mut s = variant.get_score(board, Color::White, params)
+        - variant.get_score(board, Color::Black, params);
     for _ in 0..8 {
         for _ in 0..8 {
-            let coord = unsafe { Coord::new_unchecked(n) };
-            let f = board.get(coord);
-            let mass = match f {
-                Field::WhitePiece(p) => Score::value_from_piece(*p),
-                Field::BlackPiece(p) => -Score::value_from_piece(*p),
-                _ => 0,
-            };
-            let bounty = Score::threat_bounty(*f);
-            let bounty_awards: i32 = board
-                .threat_mask
-                .get(coord)
-                .slice()
-                .iter()
-                .map(|&t| match board.get(t) {
-                    Field::WhitePiece(_) | Field::WhiteKing => bounty,
-                    Field::BlackPiece(_) | Field::BlackKing => -bounty,
-                    _ => 0,
-                })
-                .sum();
-            let (x, _y) = coord.as_xy();
-            let central_positioning_award = score::CENTRAL_PIECE_AWARDENING[x as usize];
-            let border_penalty = if x == 0 || x == 7 {
-                match *f {
-                    Field::WhitePiece(p) => Score::border_penalty(p),
-                    Field::BlackPiece(p) => -Score::border_penalty(p),
-                    _ => 0,
-                }
-            } else {
-                0
-            };
-            s += mass + bounty_awards + central_positioning_award + border_penalty;
+            let [white, black] =
+                get_square_terms(board, unsafe { Coord::new_unchecked(n) }, params);
+            s += white.total() - black.total();
             n += 1
         }
         n += 2;
@@ -84,125 +626,505 @@ fn get_white_board_score(board: &Board) -> i32 {
     s
 }
 
-fn get_sorted_moves(board: &Board, color: Color) -> LongMoveList {
+/// Evaluate the board like [`get_white_board_score`] but keep every term apart
+pub fn evaluate_explained(board: &Board, params: &EvalParams) -> EvalTrace {
+    let mut trace = EvalTrace::default();
+    for y in 0..8 {
+        for x in 0..8 {
+            let [white, black] = get_square_terms(board, Coord::from_xy(x, y), params);
+            trace.white += white;
+            trace.black += black;
+            trace.squares.push(SquareTrace { x, y, white, black });
+        }
+    }
+    trace.white.variant = board.variant().get_score(board, Color::White, params);
+    trace.black.variant = board.variant().get_score(board, Color::Black, params);
+    if board.variant() == Variant::Standard {
+        trace.endgame = endgame::get_white_score(board, params);
+    }
+    trace
+}
+
+fn get_sorted_moves(board: &Board, color: Color, ply: usize, state: &SearchState) -> LongMoveList {
     let mut lst = LongMoveList::new();
     board.enumerate_all_moves_by(color, &mut lst);
     // TODO: make benchmark to test if `sort_by_cached_key` is faster
     lst.slice_mut()
-        .sort_unstable_by_key(|m| -get_move_score(&board, m));
+        .sort_unstable_by_key(|m| -get_order_score(board, m, ply, state));
+    lst
+}
+
+fn get_order_score(board: &Board, mv: &Move, ply: usize, state: &SearchState) -> i32 {
+    if is_quiet(mv) {
+        state.get_quiet_move_score(board, mv, ply)
+    } else {
+        get_move_score(board, mv, &state.config.eval)
+    }
+}
+
+/// Sort the root moves, equally rated ones are in random order if the search is seeded
+fn get_root_moves(board: &Board, color: Color, state: &mut SearchState) -> LongMoveList {
+    let mut lst = LongMoveList::new();
+    board.enumerate_all_moves_by(color, &mut lst);
+    if let Some(rng) = state.rng.as_mut() {
+        rng.shuffle(lst.slice_mut());
+    }
+    lst.slice_mut()
+        .sort_by_key(|m| -get_order_score(board, m, 0, state));
     lst
 }
 
-fn stage_common<F: Fn(&Board, &LongMoveList, u32, [Score; 2], Color) -> Option<(Move, Score)>>(
+/// The score of a position without moves for `color`, which is not in check
+fn stalemate_score(color: Color, state: &SearchState) -> Score {
+    if color == state.color {
+        Score::Stalemate
+    } else {
+        -Score::Stalemate
+    }
+}
+
+/// Do the move and search the resulting position from the perspective of `color`
+fn search_move(
     board: &Board,
     mv: Move,
     color: Color,
     d: u32,
     win: [Score; 2],
     pv_found: bool,
-    nonescore: Score,
-    f: F,
+    state: &mut SearchState,
 ) -> Score {
     let mut board = board.clone();
     board.do_move(mv);
     board.update_aggressors(!color);
-    if d > 0 {
-        let moves = get_sorted_moves(&board, !color);
-        let cs = |win| {
-            f(&board, &moves, d - 1, win, !color)
+    state.enter_child();
+    // the search only gets down to the pieces of the tables by captures
+    let known = state
+        .config
+        .tablebase
+        .as_ref()
+        .filter(|tb| mv.is_capture() && d >= TABLEBASE_PROBE_DEPTH && tb.covers(&board))
+        .and_then(|tb| tb.probe_wdl(&board, !color));
+    let score = if board.variant_winner() == Some(color) {
+        Score::MeWins
+    } else if let Some(wdl) = known {
+        -tablebase_score(wdl, state.ply)
+    } else if d > 0 {
+        let moves = get_sorted_moves(&board, !color, state.ply, state);
+        let mut search = |[alpha, beta]: [Score; 2]| {
+            -negamax(&board, &moves, d - 1, [-beta, -alpha], !color, state)
                 .map(|(_, s)| s)
                 .unwrap_or_else(|| {
-                    if board.get_king(!color).aggressors.is_empty() {
-                        Score::Stalemate
+                    if board.variant() == Variant::Antichess {
+                        Score::MeWins
+                    } else if board.get_king(!color).aggressors.is_empty() {
+                        stalemate_score(!color, state)
                     } else {
-                        nonescore
+                        Score::EnemyWins
                     }
                 })
         };
         if pv_found {
-            let score = cs([win[0], win[0] + 1]);
+            let score = search([win[0], win[0] + 1]);
             if score > win[0] && score < win[1] {
-                cs([score, win[1]])
+                search(win)
             } else {
                 score
             }
         } else {
-            cs(win)
+            search(win)
         }
     } else {
-        let score = get_white_board_score(&board);
-        Score::Value(if (Color::White == color) == (nonescore == Score::MeWins) {
-            score
-        } else {
-            -score
-        })
-    }
+        let score = get_white_board_score(&board, &state.config.eval);
+        Score::Value(if Color::White == color { score } else { -score })
+    };
+    state.leave_child();
+    score
+}
+
+/// Let `color` skip its turn and search the reply of the opponent with reduced depth
+fn null_move_search(
+    board: &Board,
+    d: u32,
+    beta: Score,
+    color: Color,
+    state: &mut SearchState,
+) -> Option<Score> {
+    let mut board = board.clone();
+    board.en_passant_chance = None;
+    board.ply += 1;
+    board.update_aggressors(!color);
+    let d = d - 1 - state.config.null_move_reduction;
+    state.enter_child();
+    let moves = get_sorted_moves(&board, !color, state.ply, state);
+    state.in_null_move = true;
+    let score = negamax(&board, &moves, d, [-beta, -(beta + -1)], !color, state).map(|(_, s)| -s);
+    state.in_null_move = false;
+    state.leave_child();
+    score
 }
 
-fn min_stage(
+/// Search the position with `color` to move, the scores are from the perspective of `color`
+fn negamax(
     board: &Board,
     moves: &LongMoveList,
     d: u32,
     mut win: [Score; 2],
     color: Color,
+    state: &mut SearchState,
 ) -> Option<(Move, Score)> {
-    let mut min_score = None;
-    let mut pv_found = false;
-    for &mv in moves.slice() {
-        let score = stage_common(
-            &board,
-            mv,
-            color,
-            d,
-            win,
-            pv_found,
-            Score::EnemyWins,
-            max_stage,
-        );
-        if min_score.map_or_else(|| true, |(_, s)| score < s) {
-            min_score = Some((mv, score));
-            if score <= win[0] {
-                break;
+    if state.null_move_allowed(board, d, color) {
+        if let Some(score) = null_move_search(board, d, win[1], color, state) {
+            if score >= win[1] {
+                return moves.slice().first().map(|&mv| (mv, score));
             }
         }
-        if score < win[1] {
-            win[1] = score;
+    }
+    let mut best = None;
+    let mut pv_found = false;
+    for (n, &mv) in moves.slice().iter().enumerate() {
+        if state.stopped() {
+            break;
+        }
+        let reduction = state.get_late_move_reduction(board, &mv, n, d, color);
+        let mut score = search_move(board, mv, color, d - reduction, win, pv_found, state);
+        if reduction > 0 && score > win[0] {
+            score = search_move(board, mv, color, d, win, pv_found, state);
+        }
+        if best.map_or_else(|| true, |(_, s)| score > s) {
+            best = Some((mv, score));
+        }
+        if score > win[0] {
+            state.update_pv(mv);
+            if state.ply == 0 {
+                state.best = Some((score, state.pv[0].clone()));
+            }
+            win[0] = score;
             pv_found = true;
         }
+        if score >= win[1] {
+            state.store_cutoff(board, mv, d);
+            break;
+        }
     }
-    min_score
+    best
 }
 
-fn max_stage(
+/// Search the root moves on `config.threads` threads, which share the best score as alpha
+fn split_root_moves(
     board: &Board,
     moves: &LongMoveList,
-    d: u32,
-    mut win: [Score; 2],
+    depth: u32,
     color: Color,
+    state: &mut SearchState,
 ) -> Option<(Move, Score)> {
-    let mut max_score = None;
-    let mut pv_found = false;
-    for &mv in moves.slice() {
-        let score = stage_common(
-            &board,
-            mv,
-            color,
-            d,
-            win,
-            pv_found,
-            Score::MeWins,
-            min_stage,
-        );
-        if max_score.map_or_else(|| true, |(_, s)| score > s) {
-            max_score = Some((mv, score));
-            if score >= win[1] {
-                break;
+    let moves = Arc::new(moves.slice().to_vec());
+    let next = Arc::new(AtomicUsize::new(0));
+    let nodes = Arc::new(AtomicU64::new(0));
+    let seldepth = Arc::new(AtomicUsize::new(0));
+    let best = Arc::new(Mutex::new(None::<(Move, Score, Vec<Move>)>));
+    // the receiver gets disconnected once every thread is finished
+    let (sender, receiver) = channel::<()>();
+    let handles: Vec<_> = (0..state.config.threads)
+        .map(|_| {
+            let (board, moves, next, best) =
+                (board.clone(), moves.clone(), next.clone(), best.clone());
+            let (nodes, seldepth, _sender) = (nodes.clone(), seldepth.clone(), sender.clone());
+            let (config, stop, deadline) =
+                (state.config.clone(), state.stop.clone(), state.deadline);
+            std::thread::spawn(move || {
+                let mut state = SearchState::new(&config, color, None, stop);
+                state.deadline = deadline;
+                state.depth = depth;
+                while let Some(&mv) = moves.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if state.stopped() {
+                        break;
+                    }
+                    let alpha = best.lock().unwrap().as_ref().map(|&(_, s, _)| s);
+                    let win = [alpha.unwrap_or_else(Score::min), Score::max()];
+                    let searched = state.nodes;
+                    let score =
+                        search_move(&board, mv, color, depth, win, alpha.is_some(), &mut state);
+                    nodes.fetch_add(state.nodes - searched, Ordering::Relaxed);
+                    seldepth.fetch_max(state.seldepth, Ordering::Relaxed);
+                    let mut best = best.lock().unwrap();
+                    if best.as_ref().map_or_else(|| true, |&(_, s, _)| score > s) {
+                        let mut pv = vec![mv];
+                        pv.extend_from_slice(&state.pv[1]);
+                        *best = Some((mv, score, pv));
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+    let searched = state.nodes;
+    let update = |state: &mut SearchState| {
+        state.nodes = searched + nodes.load(Ordering::Relaxed);
+        state.seldepth = state.seldepth.max(seldepth.load(Ordering::Relaxed));
+    };
+    while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(REPORT_INTERVAL) {
+        update(state);
+        if let Some((_, score, pv)) = best.lock().unwrap().as_ref() {
+            state.best = Some((*score, pv.clone()));
+        }
+        state.report(false);
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    update(state);
+    // the threads give up their moves once the time is up
+    state.check_time();
+    let (mv, score, pv) = best.lock().unwrap().take()?;
+    state.pv[0] = pv;
+    Some((mv, score))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::START_FEN;
+    use std::collections::HashSet;
+
+    type Stage = fn(&Board, &LongMoveList, u32, [Score; 2], Color) -> Option<(Move, Score)>;
+
+    /// The search before negamax, which keeps the scores from the perspective of the deciding
+    /// color and alternates between maximizing and minimizing them
+    #[allow(clippy::too_many_arguments)]
+    fn old_stage_common(
+        board: &Board,
+        mv: Move,
+        color: Color,
+        d: u32,
+        win: [Score; 2],
+        pv_found: bool,
+        nonescore: Score,
+        f: Stage,
+    ) -> Score {
+        let mut board = board.clone();
+        board.do_move(mv);
+        board.update_aggressors(!color);
+        if d > 0 {
+            let moves = old_sorted_moves(&board, !color);
+            let cs = |win| {
+                f(&board, &moves, d - 1, win, !color)
+                    .map(|(_, s)| s)
+                    .unwrap_or_else(|| {
+                        if board.get_king(!color).aggressors.is_empty() {
+                            Score::Stalemate
+                        } else {
+                            nonescore
+                        }
+                    })
+            };
+            if pv_found {
+                let score = cs([win[0], win[0] + 1]);
+                if score > win[0] && score < win[1] {
+                    cs([score, win[1]])
+                } else {
+                    score
+                }
+            } else {
+                cs(win)
             }
+        } else {
+            let score = get_white_board_score(&board, &DEFAULT_EVAL_PARAMS);
+            Score::Value(if (Color::White == color) == (nonescore == Score::MeWins) {
+                score
+            } else {
+                -score
+            })
         }
-        if score > win[0] {
-            win[0] = score;
-            pv_found = true;
+    }
+
+    fn old_sorted_moves(board: &Board, color: Color) -> LongMoveList {
+        let mut lst = LongMoveList::new();
+        board.enumerate_all_moves_by(color, &mut lst);
+        lst.slice_mut()
+            .sort_by_key(|m| -get_move_score(board, m, &DEFAULT_EVAL_PARAMS));
+        lst
+    }
+
+    fn old_min_stage(
+        board: &Board,
+        moves: &LongMoveList,
+        d: u32,
+        mut win: [Score; 2],
+        color: Color,
+    ) -> Option<(Move, Score)> {
+        let mut min_score = None;
+        let mut pv_found = false;
+        for &mv in moves.slice() {
+            let score = old_stage_common(
+                board,
+                mv,
+                color,
+                d,
+                win,
+                pv_found,
+                Score::EnemyWins,
+                old_max_stage,
+            );
+            if min_score.map_or_else(|| true, |(_, s)| score < s) {
+                min_score = Some((mv, score));
+                if score <= win[0] {
+                    break;
+                }
+            }
+            if score < win[1] {
+                win[1] = score;
+                pv_found = true;
+            }
+        }
+        min_score
+    }
+
+    fn old_max_stage(
+        board: &Board,
+        moves: &LongMoveList,
+        d: u32,
+        mut win: [Score; 2],
+        color: Color,
+    ) -> Option<(Move, Score)> {
+        let mut max_score = None;
+        let mut pv_found = false;
+        for &mv in moves.slice() {
+            let score = old_stage_common(
+                board,
+                mv,
+                color,
+                d,
+                win,
+                pv_found,
+                Score::MeWins,
+                old_min_stage,
+            );
+            if max_score.map_or_else(|| true, |(_, s)| score > s) {
+                max_score = Some((mv, score));
+                if score >= win[1] {
+                    break;
+                }
+            }
+            if score > win[0] {
+                win[0] = score;
+                pv_found = true;
+            }
+        }
+        max_score
+    }
+
+    #[test]
+    fn explained_evaluation() {
+        for (fen, variant) in &[
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                Variant::Standard,
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                Variant::Standard,
+            ),
+            ("8/8/8/4k3/8/8/8/KR6 w - - 0 1", Variant::Standard),
+            ("8/8/8/4k3/8/8/8/KR6 w - - 0 1", Variant::Antichess),
+            (
+                "rnbqkbnr/ppp2ppp/8/3pp3/3PP3/8/PPP2PPP/RNBQKBNR w KQkq - 0 3",
+                Variant::KingOfTheHill,
+            ),
+            (
+                "rnbqkbnr/ppp2ppp/8/3pp3/3PP3/8/PPP2PPP/RNBQKBNR w KQkq - 0 3",
+                Variant::ThreeCheck,
+            ),
+        ] {
+            let (mut board, _) = Board::from_fen(fen).unwrap();
+            board.set_variant(*variant);
+            let trace = evaluate_explained(&board, &DEFAULT_EVAL_PARAMS);
+            let score = get_white_board_score(&board, &DEFAULT_EVAL_PARAMS);
+            assert_eq!(trace.score(), score, "{} in {}", fen, variant);
+        }
+    }
+
+    #[test]
+    fn stop_before_first_iteration() {
+        let (board, color) = Board::from_fen(START_FEN).unwrap();
+        let mut legal = LongMoveList::new();
+        board.enumerate_all_moves_by(color, &mut legal);
+        for threads in 1..=2 {
+            let config = Config {
+                threads,
+                ..DEFAULT_CONFIG
+            };
+            let stop = Arc::new(AtomicBool::new(true));
+            let result = decide_until(&board, color, config.clone(), None, stop.clone()).unwrap();
+            assert!(legal.slice().contains(&result.best_move));
+            assert_eq!(result.depth, 1);
+            let results = analyze_until(
+                &board,
+                color,
+                Config {
+                    multi_pv: 3,
+                    ..config
+                },
+                None,
+                stop,
+            );
+            assert_eq!(results.len(), 1);
+        }
+    }
+
+    #[test]
+    fn seeded_choice() {
+        // the king moves and the pawn moves are all equally good
+        let (board, color) = Board::from_fen("8/p6k/8/8/8/8/P6K/8 w - - 0 1").unwrap();
+        let decide_seeded = |seed| {
+            let config = Config {
+                depth: 2,
+                seed: Some(seed),
+                ..DEFAULT_CONFIG
+            };
+            decide(&board, color, config, None).unwrap()
+        };
+        let first = decide_seeded(0);
+        let mut moves = HashSet::new();
+        for seed in 0..16 {
+            let result = decide_seeded(seed);
+            // only moves as good as the best one are chosen without a margin
+            assert_eq!(result.score, first.score);
+            moves.insert(result.best_move.to_string());
+        }
+        assert!(moves.len() > 1);
+        assert_eq!(decide_seeded(0).best_move, first.best_move);
+    }
+
+    #[test]
+    fn negamax_matches_old_search() {
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+        ] {
+            let (board, color) = Board::from_fen(fen).unwrap();
+            for depth in 1..=3 {
+                let config = Config {
+                    depth,
+                    null_move_pruning: false,
+                    late_move_reductions: false,
+                    ..DEFAULT_CONFIG
+                };
+                let result = decide(&board, color, config, None).unwrap();
+                let moves = old_sorted_moves(&board, color);
+                let window = [Score::min(), Score::max()];
+                let (mv, score) = old_max_stage(&board, &moves, depth, window, color).unwrap();
+                assert_eq!(
+                    (result.best_move, result.score),
+                    (mv, score),
+                    "{} at depth {}",
+                    fen,
+                    depth
+                );
+            }
         }
     }
-    max_score
 }
@@ -0,0 +1,188 @@
+use crate::board::{Board, Color, Coord, Field, Piece};
+use crate::score::EvalParams;
+
+/// The bonus of an endgame that is won with correct play, but below any tablebase score
+pub const KNOWN_WIN: i32 = 10_000;
+
+/// The most pieces besides the kings any of the known endgames has
+const MAX_PIECES: usize = 2;
+
+/// The pieces of one color besides the king ordered like `Piece`
+fn pieces_of(pieces: &[(Color, Piece, Coord)], color: Color) -> Vec<(Piece, Coord)> {
+    let mut own: Vec<_> = pieces
+        .iter()
+        .filter(|(c, _, _)| *c == color)
+        .map(|&(_, p, coord)| (p, coord))
+        .collect();
+    own.sort_unstable_by_key(|(p, _)| *p as usize);
+    own
+}
+
+fn signature_of(pieces: &[(Piece, Coord)]) -> String {
+    let letters: String = pieces
+        .iter()
+        .map(|(p, _)| match p {
+            Piece::Queen => 'Q',
+            Piece::Rook => 'R',
+            Piece::Bishop => 'B',
+            Piece::Knight => 'N',
+            Piece::Pawn => 'P',
+        })
+        .collect();
+    format!("K{}", letters)
+}
+
+/// All pieces besides the kings or `None` if there are more than `max`
+fn small_material(board: &Board, max: usize) -> Option<Vec<(Color, Piece, Coord)>> {
+    let mut pieces = vec![];
+    for y in 0..8 {
+        for x in 0..8 {
+            let coord = Coord::from_xy(x, y);
+            match *board.get(coord) {
+                Field::WhitePiece(p) => pieces.push((Color::White, p, coord)),
+                Field::BlackPiece(p) => pieces.push((Color::Black, p, coord)),
+                _ => continue,
+            }
+            if pieces.len() > max {
+                return None;
+            }
+        }
+    }
+    Some(pieces)
+}
+
+/// The material of both colors like the name of an endgame table e.g. `KBNvK`
+pub fn material_signature(board: &Board) -> String {
+    let pieces = small_material(board, 32).unwrap_or_default();
+    format!(
+        "{}v{}",
+        signature_of(&pieces_of(&pieces, Color::White)),
+        signature_of(&pieces_of(&pieces, Color::Black))
+    )
+}
+
+/// How far the square is from the four central squares, 0 to 6
+pub(crate) fn center_distance(coord: Coord) -> i32 {
+    let (x, y) = coord.as_xy();
+    let d = |v: i8| (3 - v).max(v - 4) as i32;
+    d(x) + d(y)
+}
+
+fn distance(a: Coord, b: Coord) -> i32 {
+    let ((ax, ay), (bx, by)) = (a.as_xy(), b.as_xy());
+    (ax - bx).abs().max((ay - by).abs()) as i32
+}
+
+/// Drive the lone king to the edge and bring the strong king closer
+fn push_to_edge(strong_king: Coord, weak_king: Coord) -> i32 {
+    200 * center_distance(weak_king) + 100 * (7 - distance(strong_king, weak_king))
+}
+
+/// Drive the lone king into a corner of the color of the bishop, where the mate is possible
+fn push_to_bishop_corner(strong_king: Coord, weak_king: Coord, bishop: Coord) -> i32 {
+    let (x, y) = bishop.as_xy();
+    let corners = if (x + y) % 2 == 0 {
+        [Coord::from_xy(0, 0), Coord::from_xy(7, 7)]
+    } else {
+        [Coord::from_xy(7, 0), Coord::from_xy(0, 7)]
+    };
+    let corner = corners
+        .iter()
+        .map(|&c| distance(c, weak_king))
+        .min()
+        .unwrap();
+    300 * (7 - corner) + 100 * (7 - distance(strong_king, weak_king))
+}
+
+/// The score of a known endgame from the perspective of white, which replaces the general
+/// evaluation, or `None` if the material is not one of them
+pub fn get_white_score(board: &Board, params: &EvalParams) -> Option<i32> {
+    let pieces = small_material(board, MAX_PIECES)?;
+    let (white, black) = (
+        pieces_of(&pieces, Color::White),
+        pieces_of(&pieces, Color::Black),
+    );
+    let (strong, strong_pieces, weak_pieces) = if white.len() >= black.len() {
+        (Color::White, white, black)
+    } else {
+        (Color::Black, black, white)
+    };
+    let strong_king = board.get_king(strong).coord;
+    let weak_king = board.get_king(!strong).coord;
+    let material: i32 = strong_pieces
+        .iter()
+        .map(|&(p, _)| params.value_from_piece(p))
+        .sum();
+    let score = match (
+        signature_of(&strong_pieces).as_str(),
+        signature_of(&weak_pieces).as_str(),
+    ) {
+        // no mate can be forced with a single minor piece or two knights
+        ("K", "K") | ("KB", "K") | ("KN", "K") | ("KNN", "K") => return Some(0),
+        ("KQ", "K") | ("KR", "K") => KNOWN_WIN + material + push_to_edge(strong_king, weak_king),
+        ("KBN", "K") => {
+            KNOWN_WIN + material + push_to_bishop_corner(strong_king, weak_king, strong_pieces[0].1)
+        }
+        _ => return None,
+    };
+    Some(if strong == Color::White {
+        score
+    } else {
+        -score
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::DEFAULT_EVAL_PARAMS;
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(fen).unwrap().0
+    }
+
+    fn score(fen: &str) -> Option<i32> {
+        get_white_score(&board(fen), &DEFAULT_EVAL_PARAMS)
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(
+            material_signature(&board(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            )),
+            "KQRRBBNNPPPPPPPPvKQRRBBNNPPPPPPPP"
+        );
+        assert_eq!(
+            material_signature(&board("8/8/8/4k3/8/8/8/KNB5 w - - 0 1")),
+            "KBNvK"
+        );
+        assert_eq!(
+            material_signature(&board("8/8/3r4/4k3/8/8/2P5/K7 b - - 0 1")),
+            "KPvKR"
+        );
+        assert_eq!(
+            material_signature(&board("8/8/8/4k3/8/8/8/K7 w - - 0 1")),
+            "KvK"
+        );
+    }
+
+    #[test]
+    fn known_endgames() {
+        assert_eq!(score("8/8/8/4k3/8/8/8/K7 w - - 0 1"), Some(0));
+        assert_eq!(score("8/8/8/4k3/8/8/8/KB6 w - - 0 1"), Some(0));
+        assert_eq!(score("8/8/8/4k3/8/8/8/KNn5 w - - 0 1"), None);
+        assert_eq!(score("8/8/8/4k3/8/8/8/KNN5 w - - 0 1"), Some(0));
+        assert_eq!(score("8/8/8/4k3/8/8/8/KR5r w - - 0 1"), None);
+        assert_eq!(score("8/8/8/4k3/8/8/P7/K7 w - - 0 1"), None);
+        let rook = score("8/8/8/4k3/8/8/8/KR6 w - - 0 1").unwrap();
+        assert!(rook > KNOWN_WIN);
+        assert_eq!(score("kr6/8/8/8/4K3/8/8/8 w - - 0 1"), Some(-rook));
+        // the lone king is better off in the center than at the edge
+        assert!(score("7k/8/8/8/8/8/8/KQ6 w - - 0 1") > score("8/8/8/4k3/8/8/8/KQ6 w - - 0 1"));
+        // the mate with bishop and knight only works in a corner of the bishop's color
+        let right = score("k7/8/2K5/8/8/8/8/1B5N w - - 0 1").unwrap();
+        let wrong = score("7k/8/5K2/8/8/8/8/1B5N w - - 0 1").unwrap();
+        assert!(right > wrong && wrong > KNOWN_WIN);
+    }
+}
@@ -0,0 +1,474 @@
+use crate::board::{Board, Color, Coord, Field, Piece};
+use crate::crazyhouse::{Pocket, POCKET_PIECES};
+use crate::threat::{King, ThreatMask};
+use crate::variant::Variant;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone)]
+pub enum FenError {
+    FieldCount,
+    RankCount,
+    FileCount,
+    InvalidPiece(char),
+    KingCount,
+    InvalidColor,
+    InvalidCastling(char),
+    InvalidEnPassant,
+    InvalidMoveNumber,
+    InvalidPocket,
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::FieldCount => write!(f, "expected at least four space separated fields"),
+            Self::RankCount => write!(f, "expected eight ranks separated by '/'"),
+            Self::FileCount => write!(f, "expected eight files in every rank"),
+            Self::InvalidPiece(c) => write!(f, "invalid piece character '{}'", c),
+            Self::KingCount => write!(f, "expected exactly one king of each color"),
+            Self::InvalidColor => write!(f, "expected 'w' or 'b' as the active color"),
+            Self::InvalidCastling(c) => write!(f, "invalid castling character '{}'", c),
+            Self::InvalidEnPassant => write!(f, "invalid en passant target square"),
+            Self::InvalidMoveNumber => write!(f, "expected a positive fullmove number"),
+            Self::InvalidPocket => write!(f, "expected the pocket to end with ']'"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+fn field_from_char(c: char) -> Option<Field> {
+    let piece = match c.to_ascii_lowercase() {
+        'k' => {
+            return Some(if c.is_ascii_uppercase() {
+                Field::WhiteKing
+            } else {
+                Field::BlackKing
+            })
+        }
+        'q' => Piece::Queen,
+        'r' => Piece::Rook,
+        'b' => Piece::Bishop,
+        'n' => Piece::Knight,
+        'p' => Piece::Pawn,
+        _ => return None,
+    };
+    Some(if c.is_ascii_uppercase() {
+        Field::WhitePiece(piece)
+    } else {
+        Field::BlackPiece(piece)
+    })
+}
+
+fn char_from_field(f: Field) -> Option<char> {
+    let (c, white) = match f {
+        Field::WhiteKing => ('k', true),
+        Field::BlackKing => ('k', false),
+        Field::WhitePiece(p) | Field::BlackPiece(p) => (
+            match p {
+                Piece::Queen => 'q',
+                Piece::Rook => 'r',
+                Piece::Bishop => 'b',
+                Piece::Knight => 'n',
+                Piece::Pawn => 'p',
+            },
+            matches!(f, Field::WhitePiece(_)),
+        ),
+        _ => return None,
+    };
+    Some(if white { c.to_ascii_uppercase() } else { c })
+}
+
+impl Board {
+    /// The file of the rook of `color` on its first rank that is the farthest away from the
+    /// king on the given side
+    fn outermost_rook(&self, color: Color, right: bool) -> Option<i8> {
+        let (king_x, y) = self.get_king(color).coord.as_xy();
+        let rook = match color {
+            Color::White => Field::WhitePiece(Piece::Rook),
+            Color::Black => Field::BlackPiece(Piece::Rook),
+        };
+        let is_rook = |&x: &i8| *self.get(Coord::from_xy(x, y)) == rook;
+        if right {
+            (king_x + 1..8).rev().find(is_rook)
+        } else {
+            (0..king_x).find(is_rook)
+        }
+    }
+
+    /// Parse a position in Forsyth–Edwards Notation.
+    ///
+    /// The halfmove clock and the fullmove number are optional, the halfmove clock is ignored.
+    /// Castling rights may also name the file of the rook like in Shredder-FEN and X-FEN.
+    /// A pocket like `[Qp]` after the pieces makes it a Crazyhouse position, where `~` marks
+    /// promoted pieces.
+    pub fn from_fen(fen: &str) -> Result<(Self, Color), FenError> {
+        let mut fields = fen.split_whitespace();
+        let (placement, color, castling, en_passant) =
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                _ => return Err(FenError::FieldCount),
+            };
+        let (placement, pocket) = match placement.find('[') {
+            Some(i) => (
+                &placement[..i],
+                Some(
+                    placement[i + 1..]
+                        .strip_suffix(']')
+                        .ok_or(FenError::InvalidPocket)?,
+                ),
+            ),
+            None => (placement, None),
+        };
+        let mut board = Self {
+            data: [Field::Invincible; 10 * 12],
+            en_passant_chance: None,
+            threat_mask: ThreatMask::new(),
+            black_king: King::new(Color::Black),
+            white_king: King::new(Color::White),
+            ply: 0,
+            variant: Variant::Standard,
+            pockets: [Pocket::default(); 2],
+            promoted: 0,
+        };
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::RankCount);
+        }
+        let (mut white_kings, mut black_kings) = (0, 0);
+        for (rank, y) in ranks.iter().zip((0..8).rev()) {
+            let mut x = 0;
+            for c in rank.chars() {
+                if let Some(n) = c.to_digit(10) {
+                    for _ in 0..n {
+                        if x >= 8 {
+                            return Err(FenError::FileCount);
+                        }
+                        *board.get_mut(Coord::from_xy(x, y)) = Field::Empty;
+                        x += 1;
+                    }
+                } else if c == '~' && x > 0 {
+                    board.set_promoted(Coord::from_xy(x - 1, y), true);
+                } else {
+                    let field = field_from_char(c).ok_or(FenError::InvalidPiece(c))?;
+                    if x >= 8 {
+                        return Err(FenError::FileCount);
+                    }
+                    let coord = Coord::from_xy(x, y);
+                    match field {
+                        Field::WhiteKing => {
+                            white_kings += 1;
+                            board.white_king.coord = coord;
+                        }
+                        Field::BlackKing => {
+                            black_kings += 1;
+                            board.black_king.coord = coord;
+                        }
+                        _ => (),
+                    }
+                    *board.get_mut(coord) = field;
+                    x += 1;
+                }
+            }
+            if x != 8 {
+                return Err(FenError::FileCount);
+            }
+        }
+        if white_kings != 1 || black_kings != 1 {
+            return Err(FenError::KingCount);
+        }
+        if let Some(pocket) = pocket {
+            board.variant = Variant::Crazyhouse;
+            for c in pocket.chars() {
+                match field_from_char(c) {
+                    Some(Field::WhitePiece(p)) => board.get_pocket_mut(Color::White).add(p),
+                    Some(Field::BlackPiece(p)) => board.get_pocket_mut(Color::Black).add(p),
+                    _ => return Err(FenError::InvalidPiece(c)),
+                }
+            }
+        }
+        let color = match color {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(FenError::InvalidColor),
+        };
+        for king in &mut [&mut board.white_king, &mut board.black_king] {
+            king.castling_to_left = None;
+            king.castling_to_right = None;
+        }
+        if castling != "-" {
+            for c in castling.chars() {
+                let color = if c.is_ascii_uppercase() {
+                    Color::White
+                } else {
+                    Color::Black
+                };
+                let king_x = board.get_king(color).coord.as_xy().0;
+                let (file, right) = match c.to_ascii_lowercase() {
+                    'k' => (board.outermost_rook(color, true), true),
+                    'q' => (board.outermost_rook(color, false), false),
+                    f @ 'a'..='h' => {
+                        let x = f as i8 - 'a' as i8;
+                        (Some(x), x > king_x)
+                    }
+                    _ => return Err(FenError::InvalidCastling(c)),
+                };
+                let king = board.get_king_mut(color);
+                if right {
+                    king.castling_to_right = file;
+                } else {
+                    king.castling_to_left = file;
+                }
+            }
+        }
+        if en_passant != "-" {
+            let target: Coord = en_passant.parse().map_err(|_| FenError::InvalidEnPassant)?;
+            // the board remembers the pawn that moved, not the square it skipped
+            let (x, y) = target.as_xy();
+            board.en_passant_chance = Some(match y {
+                2 => Coord::from_xy(x, 3),
+                5 => Coord::from_xy(x, 4),
+                _ => return Err(FenError::InvalidEnPassant),
+            });
+        }
+        board.ply = (color == Color::Black) as u32;
+        if let Some(fullmove) = fields.nth(1) {
+            match fullmove.parse::<u32>() {
+                Ok(n) if n > 0 => board.ply = (n - 1) * 2 + (color == Color::Black) as u32,
+                _ => return Err(FenError::InvalidMoveNumber),
+            }
+        }
+        board.init_threat_mask();
+        board.update_potential_checks();
+        board.update_aggressors(Color::White);
+        board.update_aggressors(Color::Black);
+        Ok((board, color))
+    }
+
+    /// Write the position in Forsyth–Edwards Notation.
+    ///
+    /// The board does not track the halfmove clock, so it is always written as `0`. Castling
+    /// with another than the outermost rook is written with its file like in X-FEN. Crazyhouse
+    /// positions include the pockets and the promoted pieces.
+    pub fn to_fen(&self, color: Color) -> String {
+        let mut s = String::new();
+        for y in (0..8).rev() {
+            let mut empty = 0;
+            for x in 0..8 {
+                match char_from_field(*self.get(Coord::from_xy(x, y))) {
+                    Some(c) => {
+                        if empty > 0 {
+                            s.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        s.push(c);
+                        if self.variant == Variant::Crazyhouse
+                            && self.is_promoted(Coord::from_xy(x, y))
+                        {
+                            s.push('~');
+                        }
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                s.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                s.push('/');
+            }
+        }
+        if self.variant == Variant::Crazyhouse {
+            s.push('[');
+            for &(color, field) in &[
+                (Color::White, Field::WhitePiece as fn(Piece) -> Field),
+                (Color::Black, Field::BlackPiece),
+            ] {
+                for &piece in &POCKET_PIECES {
+                    for _ in 0..self.get_pocket(color).count(piece) {
+                        s.extend(char_from_field(field(piece)));
+                    }
+                }
+            }
+            s.push(']');
+        }
+        s.push_str(match color {
+            Color::White => " w ",
+            Color::Black => " b ",
+        });
+        let mut castling = String::new();
+        for &color in &[Color::White, Color::Black] {
+            let king = self.get_king(color);
+            for &(file, right) in &[
+                (king.castling_to_right, true),
+                (king.castling_to_left, false),
+            ] {
+                if let Some(x) = file {
+                    let c = match (self.outermost_rook(color, right) == Some(x), right) {
+                        (true, true) => 'k',
+                        (true, false) => 'q',
+                        (false, _) => (b'a' + x as u8) as char,
+                    };
+                    castling.push(match color {
+                        Color::White => c.to_ascii_uppercase(),
+                        Color::Black => c,
+                    });
+                }
+            }
+        }
+        s.push_str(if castling.is_empty() { "-" } else { &castling });
+        s.push(' ');
+        match self.en_passant_chance {
+            Some(coord)
+                if *self.get(coord)
+                    == match color {
+                        Color::White => Field::BlackPiece(Piece::Pawn),
+                        Color::Black => Field::WhitePiece(Piece::Pawn),
+                    } =>
+            {
+                let (x, y) = coord.as_xy();
+                let y = if y == 3 { 2 } else { 5 };
+                s.push_str(&Coord::from_xy(x, y).to_string());
+            }
+            _ => s.push('-'),
+        }
+        s.push_str(&format!(" 0 {}", self.ply / 2 + 1));
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::CHESS960_POSITIONS;
+
+    fn assert_round_trip(fen: &str) {
+        let (board, color) = Board::from_fen(fen).unwrap();
+        assert_eq!(board.to_fen(color), fen);
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in &[
+            START_FEN,
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K2R b K - 0 40",
+        ] {
+            assert_round_trip(fen);
+        }
+    }
+
+    #[test]
+    fn normalization() {
+        let normalized = |fen| {
+            let (board, color) = Board::from_fen(fen).unwrap();
+            board.to_fen(color)
+        };
+        // the halfmove clock is not tracked and both move counters are optional
+        assert_eq!(
+            normalized("4k3/8/8/8/8/8/8/4K3 b - - 37 12"),
+            "4k3/8/8/8/8/8/8/4K3 b - - 0 12"
+        );
+        assert_eq!(
+            normalized("4k3/8/8/8/8/8/8/4K3 w - -"),
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1"
+        );
+    }
+
+    #[test]
+    fn chess960() {
+        assert_eq!(
+            Board::chess960(518).unwrap().to_fen(Color::White),
+            START_FEN
+        );
+        assert!(Board::chess960(0)
+            .unwrap()
+            .to_fen(Color::White)
+            .starts_with("bbqnnrkr/"));
+        assert!(Board::chess960(959)
+            .unwrap()
+            .to_fen(Color::White)
+            .starts_with("rkrnnqbb/"));
+        assert!(Board::chess960(960).is_none());
+        let mut placements = std::collections::HashSet::new();
+        for n in 0..CHESS960_POSITIONS {
+            let fen = Board::chess960(n).unwrap().to_fen(Color::White);
+            assert_round_trip(&fen);
+            placements.insert(fen);
+        }
+        assert_eq!(placements.len(), 960);
+        // Shredder-FEN names the files of the rooks
+        let (board, color) =
+            Board::from_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9")
+                .unwrap();
+        assert_eq!(
+            board.to_fen(color),
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 0 9"
+        );
+        // X-FEN only names the file of a rook that is not the outermost one
+        assert_round_trip("1r2k3/8/8/8/8/8/8/RR2K3 w Bq - 0 1");
+    }
+
+    #[test]
+    fn crazyhouse() {
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R[NPpp] w KQkq - 0 4",
+            "rnbqkQ~1r/ppppp1pp/8/8/8/8/PPPPP1PP/RNBQKBNR[Pn] b KQq - 0 6",
+        ] {
+            let (board, color) = Board::from_fen(fen).unwrap();
+            assert_eq!(board.variant(), Variant::Crazyhouse);
+            assert_eq!(board.to_fen(color), *fen);
+        }
+        let (board, _) =
+            Board::from_fen("rnbqkQ~1r/ppppp1pp/8/8/8/8/PPPPP1PP/RNBQKBNR[QNNp] b KQq - 0 6")
+                .unwrap();
+        assert!(board.is_promoted(Coord::from_xy(5, 7)));
+        assert!(!board.is_promoted(Coord::from_xy(3, 7)));
+        assert_eq!(board.get_pocket(Color::White).count(Piece::Knight), 2);
+        assert_eq!(board.get_pocket(Color::Black).count(Piece::Pawn), 1);
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3[Q w - - 0 1"),
+            Err(FenError::InvalidPocket)
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |fen| Board::from_fen(fen).err().map(|e| e.to_string());
+        assert!(error("8/8/8/8/8/8/8/8 w").is_some());
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3/8 w - - 0 1"),
+            Err(FenError::RankCount)
+        ));
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K4 w - - 0 1"),
+            Err(FenError::FileCount)
+        ));
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4X3 w - - 0 1"),
+            Err(FenError::InvalidPiece('X'))
+        ));
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/8 w - - 0 1"),
+            Err(FenError::KingCount)
+        ));
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1"),
+            Err(FenError::InvalidColor)
+        ));
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - e9 0 1"),
+            Err(FenError::InvalidEnPassant)
+        ));
+        assert!(matches!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 0"),
+            Err(FenError::InvalidMoveNumber)
+        ));
+    }
+}
@@ -4,9 +4,20 @@
 #![feature(maybe_uninit_ref)]
 
 pub mod board;
+pub mod book;
 pub mod chessmatch;
+pub mod clock;
+pub mod crazyhouse;
 pub mod decision;
+pub mod endgame;
+pub mod fen;
 pub mod list;
 pub mod moves;
+pub mod random;
+pub mod san;
 pub mod score;
+pub mod skill;
+pub mod storage;
+pub mod syzygy;
 pub mod threat;
+pub mod variant;
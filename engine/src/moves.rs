@@ -1,11 +1,14 @@
 use crate::board::{Board, Color, Coord, Field, Piece};
 use crate::list::List;
-use crate::threat::{Direction, King};
+use crate::threat::{Direction, King, ThreatMask};
+use crate::variant::Variant;
 
 const MAX_MOVES: usize = 27;
 const MAX_PIECES: usize = 16;
+/// Every kind of piece of the pocket on every square
+const MAX_DROPS: usize = 5 * 64;
 pub type MoveList = List<Move, MAX_MOVES>;
-pub type LongMoveList = List<Move, { MAX_PIECES * MAX_MOVES }>;
+pub type LongMoveList = List<Move, { MAX_PIECES * MAX_MOVES + MAX_DROPS }>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PromotionType {
@@ -27,6 +30,8 @@ pub enum MoveType {
     Promote(Piece, PromotionType),
     EnPassant(Coord),
     Castle(Castle),
+    /// Put a piece from the pocket on the empty target, the start is the target as well
+    Drop(Piece),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -36,25 +41,57 @@ pub struct Move {
     pub move_type: MoveType,
 }
 
+impl Move {
+    pub const fn is_capture(&self) -> bool {
+        matches!(
+            self.move_type,
+            MoveType::Capture
+                | MoveType::EnPassant(_)
+                | MoveType::Promote(_, PromotionType::Capture)
+        )
+    }
+}
+
+/// Long algebraic notation as used by UCI e.g. `e2e4`, `e7e8q` or `N@f3`
+impl std::fmt::Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let c = |piece| match piece {
+            Piece::Queen => 'q',
+            Piece::Rook => 'r',
+            Piece::Bishop => 'b',
+            Piece::Knight => 'n',
+            Piece::Pawn => 'p',
+        };
+        if let MoveType::Drop(piece) = self.move_type {
+            return write!(f, "{}@{}", c(piece).to_ascii_uppercase(), self.end);
+        }
+        write!(f, "{}{}", self.start, self.end)?;
+        if let MoveType::Promote(piece, _) = self.move_type {
+            write!(f, "{}", c(piece))?;
+        }
+        Ok(())
+    }
+}
+
 impl Board {
     fn is_bad_king_move(&self, target: Coord, color: Color) -> bool {
+        let (kx, ky) = self.get_king(color).coord.as_xy();
+        let (x, y) = target.as_xy();
         for &threat in self.get_king(color).aggressors.slice() {
-            let f = self.get(threat);
             if target == threat {
                 continue;
             }
-            let (x1, y1) = target.as_xy();
-            let (x2, y2) = threat.as_xy();
-            let diag = || (x1 - x2).abs() == (y1 - y2).abs();
-            let hor = || x1 == x2 || y1 == y2;
-            if match f {
-                Field::BlackPiece(Piece::Bishop) | Field::WhitePiece(Piece::Bishop) => diag(),
-                Field::BlackPiece(Piece::Rook) | Field::WhitePiece(Piece::Rook) => hor(),
-                Field::BlackPiece(Piece::Queen) | Field::WhitePiece(Piece::Queen) => {
-                    diag() || hor()
-                }
-                _ => false,
-            } {
+            let (tx, ty) = threat.as_xy();
+            if matches!(
+                self.get(threat),
+                Field::BlackPiece(Piece::Bishop)
+                    | Field::WhitePiece(Piece::Bishop)
+                    | Field::BlackPiece(Piece::Rook)
+                    | Field::WhitePiece(Piece::Rook)
+                    | Field::BlackPiece(Piece::Queen)
+                    | Field::WhitePiece(Piece::Queen)
+            ) && (x - kx, y - ky) == ((kx - tx).signum(), (ky - ty).signum())
+            {
                 return true;
             }
         }
@@ -102,36 +139,116 @@ impl Board {
         }
         if !is_in_check {
             let king = self.get_king(color);
-            let mut castle_d = |d1, d2, d3| {
-                if let (Some((rook_target, Field::Empty)), Some((king_target, Field::Empty))) = (
-                    self.get_if_safe(coord.rel(d1, 0)),
-                    self.get_if_safe(coord.rel(d2, 0)),
-                ) {
-                    if let (Color::Black, Some((rook_pos, Field::BlackPiece(Piece::Rook))))
-                    | (Color::White, Some((rook_pos, Field::WhitePiece(Piece::Rook)))) =
-                        (color, self.get_if_safe(coord.rel(d3, 0)))
+            if let Some(rook_x) = king.castling_to_right {
+                self.list_castling_move(coord, color, rook_x, true, into);
+            }
+            if let Some(rook_x) = king.castling_to_left {
+                self.list_castling_move(coord, color, rook_x, false, into);
+            }
+        }
+    }
+
+    /// Castle with the rook on the file `rook_x` following the rules of Chess960, which
+    /// contain the classical ones: the king ends on the g or c file and the rook next to it
+    pub(crate) fn list_castling_move<const N: usize>(
+        &self,
+        coord: Coord,
+        color: Color,
+        rook_x: i8,
+        right: bool,
+        into: &mut List<Move, N>,
+    ) {
+        let (king_x, y) = coord.as_xy();
+        let (king_target_x, rook_target_x) = if right { (6, 5) } else { (2, 3) };
+        let rook = match color {
+            Color::White => Field::WhitePiece(Piece::Rook),
+            Color::Black => Field::BlackPiece(Piece::Rook),
+        };
+        if *self.get(Coord::from_xy(rook_x, y)) != rook {
+            return;
+        }
+        let span = |a: i8, b: i8| a.min(b)..=a.max(b);
+        // apart from the king and the rook every square they cross has to be empty
+        let blocked = span(king_x, king_target_x)
+            .chain(span(rook_x, rook_target_x))
+            .any(|x| x != king_x && x != rook_x && *self.get(Coord::from_xy(x, y)) != Field::Empty);
+        let crosses_threat = || {
+            span(king_x, king_target_x)
+                .any(|x| x != king_x && self.get_threatened_by(Coord::from_xy(x, y), color))
+        };
+        if blocked || crosses_threat() || self.is_revealed_by_rook(color, coord, rook_x, right) {
+            return;
+        }
+        into.append(Move {
+            start: coord,
+            end: Coord::from_xy(king_target_x, y),
+            move_type: MoveType::Castle(Castle {
+                rook_pos: Coord::from_xy(rook_x, y),
+                rook_target: Coord::from_xy(rook_target_x, y),
+            }),
+        })
+    }
+
+    /// Whether the castling rook shields the target of the king from a rook or queen on the
+    /// same rank, which is only possible in Chess960
+    fn is_revealed_by_rook(&self, color: Color, king: Coord, rook_x: i8, right: bool) -> bool {
+        let (king_x, y) = king.as_xy();
+        let (mut x, dx) = if right { (7, 1) } else { (1, -1) };
+        while (0..8).contains(&x) {
+            if x != king_x && x != rook_x {
+                match self.get(Coord::from_xy(x, y)) {
+                    Field::Empty => (),
+                    Field::WhitePiece(Piece::Rook) | Field::WhitePiece(Piece::Queen)
+                        if color == Color::Black =>
                     {
-                        if !self.get_threatened_by(rook_target, color)
-                            && !self.get_threatened_by(king_target, color)
-                        {
-                            into.append(Move {
-                                start: coord,
-                                end: king_target,
-                                move_type: MoveType::Castle(Castle {
-                                    rook_pos,
-                                    rook_target,
-                                }),
-                            })
-                        }
+                        return true
+                    }
+                    Field::BlackPiece(Piece::Rook) | Field::BlackPiece(Piece::Queen)
+                        if color == Color::White =>
+                    {
+                        return true
                     }
+                    _ => return false,
                 }
-            };
-            if king.castling_to_right {
-                castle_d(1, 2, 3);
             }
-            if king.castling_to_left {
-                castle_d(-1, -2, -4);
+            x += dx;
+        }
+        false
+    }
+
+    /// Whether capturing en passant removes both pawns between the king and a rook or queen
+    /// on the same rank, which the pin detection of single pieces misses
+    fn is_revealed_by_en_passant(&self, color: Color, coord: Coord, target: Coord) -> bool {
+        let (king_x, king_y) = self.get_king(color).coord.as_xy();
+        let (x, y) = coord.as_xy();
+        if king_y != y {
+            return false;
+        }
+        let dx = (x - king_x).signum();
+        let first = (1..)
+            .map(|i| king_x + i * dx)
+            .take_while(|x| (0..8).contains(x))
+            .map(|x| Coord::from_xy(x, y))
+            .filter(|&square| square != coord && square != target)
+            .map(|square| self.get(square))
+            .find(|field| !matches!(field, Field::Empty));
+        match first {
+            Some(Field::WhitePiece(Piece::Rook)) | Some(Field::WhitePiece(Piece::Queen)) => {
+                color == Color::Black
+            }
+            Some(Field::BlackPiece(Piece::Rook)) | Some(Field::BlackPiece(Piece::Queen)) => {
+                color == Color::White
             }
+            _ => false,
+        }
+    }
+
+    /// Whether a piece of `color` may capture `field`, which is a king only in Antichess
+    pub(crate) fn is_capturable(&self, field: Field, color: Color) -> bool {
+        if self.variant == Variant::Antichess {
+            field.is_color_piece_include_king(color)
+        } else {
+            field.is_color_piece(color)
         }
     }
 
@@ -146,13 +263,7 @@ impl Board {
         let endline_reaching = forward1_coord.endline() == Some(color);
         let append_mut_if_endline = |into: &mut List<Move, N>, start, end, promotion_type| {
             if endline_reaching {
-                for &piece in &[
-                    Piece::Queen,
-                    Piece::Rook,
-                    Piece::Knight,
-                    Piece::Bishop,
-                    Piece::Pawn,
-                ] {
+                for &piece in &[Piece::Queen, Piece::Rook, Piece::Knight, Piece::Bishop] {
                     into.append(Move {
                         start,
                         end,
@@ -184,15 +295,17 @@ impl Board {
         }
         for &target_coord in &[coord.rel(-1, delta), coord.rel(1, delta)] {
             if let Some((target_coord, field)) = self.get_if_safe(target_coord) {
-                if field.is_color_piece(color) {
+                if self.is_capturable(field, color) {
                     append_mut_if_endline(into, coord, target_coord, PromotionType::Capture)
                 }
             }
         }
         if let Some(target_coord) = self.en_passant_chance {
             if let Some((jump_coord, _)) = self.get_if_safe(target_coord.rel(0, delta)) {
-                if target_coord.as_unsafe() == coord.rel(1, 0)
-                    || target_coord.as_unsafe() == coord.rel(-1, 0)
+                if (target_coord.as_unsafe() == coord.rel(1, 0)
+                    || target_coord.as_unsafe() == coord.rel(-1, 0))
+                    && !(self.variant.has_standard_moves()
+                        && self.is_revealed_by_en_passant(color, coord, target_coord))
                 {
                     into.append(Move {
                         start: coord,
@@ -222,7 +335,7 @@ impl Board {
                             end: target_coord_safe,
                             move_type: MoveType::Regular,
                         })
-                    } else if field.is_color_piece(color) {
+                    } else if self.is_capturable(field, color) {
                         into.append(Move {
                             start: coord,
                             end: target_coord_safe,
@@ -281,7 +394,7 @@ impl Board {
                         end: target_coord,
                         move_type: MoveType::Regular,
                     })
-                } else if field.is_color_piece(color) {
+                } else if self.is_capturable(field, color) {
                     into.append(Move {
                         start: coord,
                         end: target_coord,
@@ -364,6 +477,14 @@ impl Board {
 
     pub fn enumerate_moves(&self, color: Color, coord: Coord) -> MoveList {
         let mut list = MoveList::new();
+        if !self.variant.has_standard_moves() {
+            let mut all = LongMoveList::new();
+            self.enumerate_variant_moves(color, &mut all);
+            for &mv in all.slice().iter().filter(|mv| mv.start == coord) {
+                list.append(mv);
+            }
+            return list;
+        }
         let king = self.get_king(color);
         if king.aggressors.is_empty() {
             self.add_moves(coord, &mut list)
@@ -375,6 +496,9 @@ impl Board {
     }
 
     pub fn enumerate_all_moves_by(&self, color: Color, list: &mut LongMoveList) {
+        if !self.variant.has_standard_moves() {
+            return self.enumerate_variant_moves(color, list);
+        }
         let king = self.get_king(color);
         let f = if king.aggressors.is_empty() {
             Self::add_moves
@@ -394,6 +518,13 @@ impl Board {
             }
             n += 2;
         }
+        if self.variant == Variant::Crazyhouse {
+            let n = list.slice().len();
+            self.list_drops(color, list);
+            if !king.aggressors.is_empty() {
+                self.filter_checks(color, n, list);
+            }
+        }
     }
 
     pub fn is_potential_check(&self, king: &King, mv: &Move) -> bool {
@@ -430,32 +561,18 @@ impl Board {
     }
 
     fn is_check_saving_piece(&self, threat: Coord, piece: Piece, king: &King, mv: &Move) -> bool {
-        if mv.end == threat {
+        if mv.end == threat || mv.move_type == MoveType::EnPassant(threat) {
             return true;
         }
         if let Piece::Pawn | Piece::Knight = piece {
             return false;
         }
-        if !self.threat_mask.get(mv.end).slice().contains(&threat) {
-            return false;
-        }
-        let etok = king.coord.0.get() - mv.end.0.get();
-        let ttoe = mv.end.0.get() - threat.0.get();
-        if etok.is_positive() != ttoe.is_positive() {
-            return false;
-        }
-        let (a, b) = (etok.abs(), ttoe.abs());
-        let mf = |n| a.abs() % n == 0 && b.abs() % n == 0;
-        let eq = |x, y, z| x == y && y == z;
-        etok == ttoe
-            || mf(9)
-            || mf(10)
-            || mf(11)
-            || eq(
-                king.coord.0.get() / 10,
-                mv.end.0.get() / 10,
-                threat.0.get() / 10,
-            )
+        let ((kx, ky), (tx, ty)) = (king.coord.as_xy(), threat.as_xy());
+        let (dx, dy) = ((kx - tx).signum(), (ky - ty).signum());
+        (1..)
+            .map(|i| (tx + i * dx, ty + i * dy))
+            .take_while(|&square| square != (kx, ky))
+            .any(|(x, y)| Coord::from_xy(x, y) == mv.end)
     }
 
     pub fn is_check_saving(&self, color: Color, mv: &Move) -> bool {
@@ -491,35 +608,66 @@ impl Board {
         list.filter(start, |v| !self.is_potential_check(king, v))
     }
 
+    /// Moving a castling rook or capturing it gives up castling to its side
+    pub(crate) fn revoke_castling_with(&mut self, coord: Coord) {
+        let (x, y) = coord.as_xy();
+        let king = match y {
+            0 => &mut self.white_king,
+            7 => &mut self.black_king,
+            _ => return,
+        };
+        if king.castling_to_left == Some(x) {
+            king.castling_to_left = None;
+        }
+        if king.castling_to_right == Some(x) {
+            king.castling_to_right = None;
+        }
+    }
+
+    /// Whether the king or the rook of a Chess960 castling lands on a square that one of
+    /// them leaves, which the incremental threat updates cannot handle
+    fn is_overlapping_castle(mv: &Move) -> bool {
+        match mv.move_type {
+            MoveType::Castle(c) => {
+                mv.end == mv.start
+                    || mv.end == c.rook_pos
+                    || c.rook_target == mv.start
+                    || c.rook_target == c.rook_pos
+            }
+            _ => false,
+        }
+    }
+
     pub fn do_move(&mut self, mv: Move) {
-        self.remove_threat_mask_piece_moves(mv);
+        let color = match self.get(mv.start) {
+            Field::BlackPiece(_) | Field::BlackKing => Color::Black,
+            Field::WhitePiece(_) | Field::WhiteKing => Color::White,
+            // only drops start on an empty square
+            _ => self.color_to_move(),
+        };
+        self.ply += 1;
+        if self.variant == Variant::Crazyhouse {
+            self.update_pockets(mv, color);
+        }
+        let explodes = self.variant == Variant::Atomic && mv.is_capture();
+        let rebuild = explodes || Self::is_overlapping_castle(&mv);
+        if !rebuild {
+            self.remove_threat_mask_piece_moves(mv);
+        }
         match self.get(mv.start) {
             Field::BlackKing => {
                 self.black_king.coord = mv.end;
-                self.black_king.castling_to_left = false;
-                self.black_king.castling_to_right = false;
+                self.black_king.castling_to_left = None;
+                self.black_king.castling_to_right = None;
             }
             Field::WhiteKing => {
                 self.white_king.coord = mv.end;
-                self.white_king.castling_to_left = false;
-                self.white_king.castling_to_right = false;
-            }
-            Field::WhitePiece(Piece::Rook) => {
-                if mv.start == Coord::from_xy(0, 0) {
-                    self.white_king.castling_to_left = false;
-                } else {
-                    self.white_king.castling_to_right = false;
-                }
-            }
-            Field::BlackPiece(Piece::Rook) => {
-                if mv.start == Coord::from_xy(0, 7) {
-                    self.black_king.castling_to_left = false;
-                } else {
-                    self.black_king.castling_to_right = false;
-                }
+                self.white_king.castling_to_left = None;
+                self.white_king.castling_to_right = None;
             }
-            _ => (),
+            _ => self.revoke_castling_with(mv.start),
         };
+        self.revoke_castling_with(mv.end);
         self.en_passant_chance = None;
         match mv.move_type {
             MoveType::Regular => {
@@ -548,11 +696,131 @@ impl Board {
                 rook_pos,
                 rook_target,
             }) => {
-                self.move_piece(mv.start, mv.end, Field::Empty);
-                self.move_piece(rook_pos, rook_target, Field::Empty);
+                let king = self.pop_field(mv.start, Field::Empty);
+                let rook = self.pop_field(rook_pos, Field::Empty);
+                self.pop_field(mv.end, king);
+                self.pop_field(rook_target, rook);
             }
+            MoveType::Drop(piece) => {
+                self.pop_field(
+                    mv.end,
+                    match color {
+                        Color::White => Field::WhitePiece(piece),
+                        Color::Black => Field::BlackPiece(piece),
+                    },
+                );
+            }
+        }
+        if explodes {
+            self.explode(mv.end);
+        }
+        if rebuild {
+            self.threat_mask = ThreatMask::new();
+            self.init_threat_mask();
+        } else {
+            self.update_threat_mask_with(mv);
+        }
+        if self.variant == Variant::ThreeCheck && self.is_in_check(!color) {
+            self.get_king_mut(!color).checks += 1;
         }
-        self.update_threat_mask_with(mv);
         self.update_potential_checks();
     }
+    /// Count the leaf nodes of the legal move tree `depth` plies deep, which checks the move
+    /// generation against known counts
+    pub fn perft(&self, color: Color, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut moves = LongMoveList::new();
+        self.enumerate_all_moves_by(color, &mut moves);
+        if depth == 1 {
+            return moves.slice().len() as u64;
+        }
+        moves
+            .slice()
+            .iter()
+            .map(|&mv| {
+                let mut board = self.clone();
+                board.do_move(mv);
+                board.update_aggressors(!color);
+                board.perft(!color, depth - 1)
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::variant::Variant;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    fn assert_perft(fen: &str, variant: Variant, expected: &[u64]) {
+        let (mut board, color) = Board::from_fen(fen).unwrap();
+        board.set_variant(variant);
+        for (depth, &nodes) in (1..).zip(expected) {
+            assert_eq!(
+                board.perft(color, depth),
+                nodes,
+                "{} at depth {}",
+                fen,
+                depth
+            );
+        }
+    }
+
+    #[test]
+    fn perft_standard() {
+        let positions: &[(&str, &[u64])] = &[
+            (START, &[20, 400, 8902, 197281]),
+            (KIWIPETE, &[48, 2039, 97862]),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                &[14, 191, 2812, 43238],
+            ),
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                &[6, 264, 9467, 422333],
+            ),
+            (
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                &[44, 1486, 62379],
+            ),
+            (
+                "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                &[46, 2079, 89890],
+            ),
+        ];
+        for (fen, expected) in positions {
+            assert_perft(fen, Variant::Standard, expected);
+        }
+    }
+
+    #[test]
+    fn perft_chess960() {
+        assert_perft(
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            Variant::Standard,
+            &[21, 528, 12189],
+        );
+        assert_perft(
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            Variant::Standard,
+            &[21, 807, 18002],
+        );
+    }
+
+    #[test]
+    fn perft_atomic() {
+        assert_perft(START, Variant::Atomic, &[20, 400, 8902, 197326]);
+        assert_perft(KIWIPETE, Variant::Atomic, &[48, 1939, 88298]);
+    }
+
+    #[test]
+    fn perft_antichess() {
+        assert_perft(START, Variant::Antichess, &[20, 400, 8067, 153299]);
+        assert_perft(KIWIPETE, Variant::Antichess, &[8, 62, 487]);
+    }
 }
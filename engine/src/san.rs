@@ -0,0 +1,286 @@
+use crate::board::{Board, Color, Field, Piece};
+use crate::moves::{LongMoveList, Move, MoveType};
+
+#[derive(Debug, Clone)]
+pub enum SanError {
+    Empty,
+    InvalidPiece(char),
+    InvalidSquare,
+    IllegalMove,
+    AmbiguousMove,
+}
+
+impl std::fmt::Display for SanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "expected a move"),
+            Self::InvalidPiece(c) => write!(f, "invalid piece character '{}'", c),
+            Self::InvalidSquare => write!(f, "expected a target square e.g. 'e4'"),
+            Self::IllegalMove => write!(f, "no legal move matches"),
+            Self::AmbiguousMove => write!(f, "more than one legal move matches"),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+fn piece_from_char(c: char) -> Result<Piece, SanError> {
+    Ok(match c {
+        'Q' => Piece::Queen,
+        'R' => Piece::Rook,
+        'B' => Piece::Bishop,
+        'N' => Piece::Knight,
+        _ => return Err(SanError::InvalidPiece(c)),
+    })
+}
+
+const fn piece_char(piece: Piece) -> char {
+    match piece {
+        Piece::Queen => 'Q',
+        Piece::Rook => 'R',
+        Piece::Bishop => 'B',
+        Piece::Knight => 'N',
+        Piece::Pawn => 'P',
+    }
+}
+
+/// The moving piece of a field, `None` stands for the king
+fn moving_piece(field: &Field) -> Option<Piece> {
+    match field {
+        Field::WhitePiece(p) | Field::BlackPiece(p) => Some(*p),
+        _ => None,
+    }
+}
+
+impl Board {
+    /// Find the legal move written in standard algebraic notation e.g. `Nbd7`, `exd8=Q+`,
+    /// `O-O` or the Crazyhouse drop `N@f3`
+    pub fn parse_san(&self, color: Color, s: &str) -> Result<Move, SanError> {
+        let s = s.trim_end_matches(&['+', '#', '!', '?'][..]);
+        let mut moves = LongMoveList::new();
+        self.enumerate_all_moves_by(color, &mut moves);
+        let king_x = self.get_king(color).coord.as_xy().0;
+        let castle = match s {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            "" => return Err(SanError::Empty),
+            _ => None,
+        };
+        if let Some(kingside) = castle {
+            return moves
+                .slice()
+                .iter()
+                .find(|m| match m.move_type {
+                    MoveType::Castle(c) => (c.rook_pos.as_xy().0 > king_x) == kingside,
+                    _ => false,
+                })
+                .copied()
+                .ok_or(SanError::IllegalMove);
+        }
+        if let Some(i) = s.find('@') {
+            let piece = match s[..i].chars().next() {
+                None | Some('P') => Piece::Pawn,
+                Some(c) => piece_from_char(c)?,
+            };
+            let target = s[i + 1..].parse().map_err(|_| SanError::InvalidSquare)?;
+            return moves
+                .slice()
+                .iter()
+                .find(|m| m.end == target && m.move_type == MoveType::Drop(piece))
+                .copied()
+                .ok_or(SanError::IllegalMove);
+        }
+
+        let (s, promotion) = match s.rfind('=') {
+            Some(i) => {
+                let c = s[i + 1..].chars().next().ok_or(SanError::Empty)?;
+                (&s[..i], Some(piece_from_char(c)?))
+            }
+            None => match s.chars().last() {
+                Some(c) if c.is_ascii_uppercase() => (&s[..s.len() - 1], Some(piece_from_char(c)?)),
+                _ => (s, None),
+            },
+        };
+        let (piece, s) = match s.chars().next() {
+            Some('K') => (None, &s[1..]),
+            Some(c) if c.is_ascii_uppercase() => (Some(piece_from_char(c)?), &s[1..]),
+            Some(_) => (Some(Piece::Pawn), s),
+            None => return Err(SanError::Empty),
+        };
+        if s.len() < 2 || !s.is_char_boundary(s.len() - 2) {
+            return Err(SanError::InvalidSquare);
+        }
+        let target = s[s.len() - 2..]
+            .parse()
+            .map_err(|_| SanError::InvalidSquare)?;
+        let (mut file, mut rank) = (None, None);
+        for c in s[..s.len() - 2].chars() {
+            match c {
+                'a'..='h' => file = Some(c as i8 - 'a' as i8),
+                '1'..='8' => rank = Some(c as i8 - '1' as i8),
+                'x' | '-' => (),
+                _ => return Err(SanError::InvalidSquare),
+            }
+        }
+
+        let mut candidates = moves.slice().iter().filter(|m| {
+            let (x, y) = m.start.as_xy();
+            m.end == target
+                && moving_piece(self.get(m.start)) == piece
+                && file.unwrap_or(x) == x
+                && rank.unwrap_or(y) == y
+                && match m.move_type {
+                    MoveType::Promote(p, _) => Some(p) == promotion,
+                    MoveType::Castle(_) | MoveType::Drop(_) => false,
+                    _ => promotion.is_none(),
+                }
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some(&mv), None) => Ok(mv),
+            (Some(_), Some(_)) => Err(SanError::AmbiguousMove),
+            (None, _) => Err(SanError::IllegalMove),
+        }
+    }
+
+    /// Write the legal move `mv` of `color` in standard algebraic notation, the inverse of
+    /// `parse_san`
+    pub fn to_san(&self, color: Color, mv: Move) -> String {
+        let mut moves = LongMoveList::new();
+        self.enumerate_all_moves_by(color, &mut moves);
+        let mut san = match mv.move_type {
+            MoveType::Castle(c) if c.rook_pos.as_xy().0 > self.get_king(color).coord.as_xy().0 => {
+                "O-O".to_string()
+            }
+            MoveType::Castle(_) => "O-O-O".to_string(),
+            MoveType::Drop(piece) => format!("{}@{}", piece_char(piece), mv.end),
+            _ => {
+                let piece = moving_piece(self.get(mv.start));
+                let (x, y) = mv.start.as_xy();
+                let others: Vec<(i8, i8)> = moves
+                    .slice()
+                    .iter()
+                    .filter(|m| {
+                        m.end == mv.end
+                            && m.start != mv.start
+                            && moving_piece(self.get(m.start)) == piece
+                            && !matches!(m.move_type, MoveType::Castle(_) | MoveType::Drop(_))
+                    })
+                    .map(|m| m.start.as_xy())
+                    .collect();
+                let file = (b'a' + x as u8) as char;
+                let mut san = match piece {
+                    None => "K".to_string(),
+                    Some(Piece::Pawn) if mv.is_capture() => file.to_string(),
+                    Some(Piece::Pawn) => String::new(),
+                    Some(p) if others.is_empty() => piece_char(p).to_string(),
+                    Some(p) if others.iter().all(|o| o.0 != x) => {
+                        format!("{}{}", piece_char(p), file)
+                    }
+                    Some(p) if others.iter().all(|o| o.1 != y) => {
+                        format!("{}{}", piece_char(p), y + 1)
+                    }
+                    Some(p) => format!("{}{}", piece_char(p), mv.start),
+                };
+                if mv.is_capture() {
+                    san.push('x');
+                }
+                san.push_str(&mv.end.to_string());
+                if let MoveType::Promote(p, _) = mv.move_type {
+                    san.push('=');
+                    san.push(piece_char(p));
+                }
+                san
+            }
+        };
+        let mut board = self.clone();
+        board.do_move(mv);
+        board.update_aggressors(!color);
+        if board.variant_winner() == Some(color) {
+            san.push('#');
+        } else if !board.get_king(!color).aggressors.is_empty() {
+            let mut replies = LongMoveList::new();
+            board.enumerate_all_moves_by(!color, &mut replies);
+            san.push(if replies.is_empty() { '#' } else { '+' });
+        }
+        san
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::{Board, Color};
+    use crate::moves::LongMoveList;
+
+    fn assert_round_trip(fen: &str) {
+        let (board, color) = Board::from_fen(fen).unwrap();
+        let mut moves = LongMoveList::new();
+        board.enumerate_all_moves_by(color, &mut moves);
+        for &mv in moves.slice() {
+            let san = board.to_san(color, mv);
+            assert_eq!(
+                board.parse_san(color, &san).ok(),
+                Some(mv),
+                "{} in {}",
+                san,
+                fen
+            );
+        }
+    }
+
+    fn assert_san(fen: &str, input: &str, expected: &str) {
+        let (board, color) = Board::from_fen(fen).unwrap();
+        let mv = board.parse_san(color, input).unwrap();
+        assert_eq!(board.to_san(color, mv), expected);
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            "rnbqkb1r/pppp1ppp/5n2/4p3/4P3/8/PPPP1PPP/RNBQKBNR[Np] w KQkq - 0 3",
+        ] {
+            assert_round_trip(fen);
+        }
+    }
+
+    #[test]
+    fn notation() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_san(start, "e2e4", "e4");
+        assert_san(start, "Ng1f3", "Nf3");
+        assert_san(
+            "rnbqkbnr/pppppppp/8/8/8/5N2/PPP1PPPP/RNBQKB1R w KQkq - 0 1",
+            "Nbd2",
+            "Nbd2",
+        );
+        assert_san(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "0-0-0",
+            "O-O-O",
+        );
+        assert_san(
+            "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq g3 0 2",
+            "Qh4",
+            "Qh4#",
+        );
+        assert_san(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "dxc8Q",
+            "dxc8=Q",
+        );
+        assert_san(
+            "rnbqkb1r/pppp1ppp/5n2/4p3/4P3/8/PPPP1PPP/RNBQKBNR[Np] w KQkq - 0 3",
+            "N@d5",
+            "N@d5",
+        );
+        assert_eq!(
+            Board::new().parse_san(Color::White, "e5").ok(),
+            None,
+            "a pawn cannot move three squares"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::board::{Field, Piece};
 use core::cmp::Ordering;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 pub const QUEEN_VALUE: i32 = 9500;
 pub const ROOK_VALUE: i32 = 5630;
@@ -23,54 +25,249 @@ pub const BORDER_PAWN_PENALTY: i32 = 0;
 
 pub const CASTLING_MOVE_SCORE: i32 = 900;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Score {
-    MeWins,
-    EnemyWins,
-    Stalemate,
-    Value(i32),
+pub const DEFAULT_EVAL_PARAMS: EvalParams = EvalParams {
+    queen_value: QUEEN_VALUE,
+    rook_value: ROOK_VALUE,
+    bishop_value: BISHOP_VALUE,
+    knight_value: KNIGHT_VALUE,
+    pawn_value: PAWN_VALUE,
+    queen_threatened_value: QUEEN_THREATENED_VALUE,
+    rook_threatened_value: ROOK_THREATENED_VALUE,
+    bishop_threatened_value: BISHOP_THREATENED_VALUE,
+    knight_threatened_value: KNIGHT_THREATENED_VALUE,
+    pawn_threatened_value: PAWN_THREATENED_VALUE,
+    king_threatened_value: 0,
+    empty_threatened_value: EMPTY_THREATENED_VALUE,
+    central_piece_awardening: [
+        CENTRAL_PIECE_AWARDENING[0],
+        CENTRAL_PIECE_AWARDENING[1],
+        CENTRAL_PIECE_AWARDENING[2],
+        CENTRAL_PIECE_AWARDENING[3],
+        CENTRAL_PIECE_AWARDENING[4],
+        CENTRAL_PIECE_AWARDENING[5],
+        CENTRAL_PIECE_AWARDENING[6],
+        CENTRAL_PIECE_AWARDENING[7],
+    ],
+    border_queen_penalty: BORDER_QUEEN_PENALTY,
+    border_rook_penalty: BORDER_ROOK_PENALTY,
+    border_bishop_penalty: BORDER_BISHOP_PENALTY,
+    border_knight_penalty: BORDER_KNIGHT_PENALTY,
+    border_pawn_penalty: BORDER_PAWN_PENALTY,
+    castling_move_score: CASTLING_MOVE_SCORE,
+    capture_attacker_shift: 2,
+};
+
+/// All tunable weights of the evaluation and the move ordering.
+///
+/// Missing fields in a parameter file fall back to [`DEFAULT_EVAL_PARAMS`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvalParams {
+    pub queen_value: i32,
+    pub rook_value: i32,
+    pub bishop_value: i32,
+    pub knight_value: i32,
+    pub pawn_value: i32,
+
+    pub queen_threatened_value: i32,
+    pub rook_threatened_value: i32,
+    pub bishop_threatened_value: i32,
+    pub knight_threatened_value: i32,
+    pub pawn_threatened_value: i32,
+    pub king_threatened_value: i32,
+    pub empty_threatened_value: i32,
+
+    /// Award for every square, indexed by its file
+    pub central_piece_awardening: [i32; 8],
+    pub border_queen_penalty: i32,
+    pub border_rook_penalty: i32,
+    pub border_bishop_penalty: i32,
+    pub border_knight_penalty: i32,
+    pub border_pawn_penalty: i32,
+
+    pub castling_move_score: i32,
+    /// The value of a capturing piece is shifted right by this amount
+    /// before it is subtracted from the value of the captured piece
+    pub capture_attacker_shift: u32,
 }
 
-impl Score {
-    pub const fn min() -> Self {
-        Self::EnemyWins
+impl Default for EvalParams {
+    fn default() -> Self {
+        DEFAULT_EVAL_PARAMS
     }
+}
 
-    pub const fn max() -> Self {
-        Self::MeWins
+#[derive(Debug)]
+pub enum EvalParamsError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    UnknownFormat,
+}
+
+impl std::fmt::Display for EvalParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "could not read parameter file: {}", e),
+            Self::Toml(e) => write!(f, "invalid toml parameter file: {}", e),
+            Self::Json(e) => write!(f, "invalid json parameter file: {}", e),
+            Self::UnknownFormat => write!(f, "expected a '.toml' or '.json' parameter file"),
+        }
+    }
+}
+
+impl std::error::Error for EvalParamsError {}
+
+impl EvalParams {
+    pub fn from_toml_str(s: &str) -> Result<Self, EvalParamsError> {
+        toml::from_str(s).map_err(EvalParamsError::Toml)
+    }
+
+    pub fn from_json_str(s: &str) -> Result<Self, EvalParamsError> {
+        serde_json::from_str(s).map_err(EvalParamsError::Json)
+    }
+
+    /// Load the parameters from a file, the format is chosen by the file extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, EvalParamsError> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(EvalParamsError::Io)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml_str(&s),
+            Some("json") => Self::from_json_str(&s),
+            _ => Err(EvalParamsError::UnknownFormat),
+        }
+    }
+
+    pub fn to_toml_string(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
     }
 
-    pub const fn value_from_piece(p: Piece) -> i32 {
+    pub const fn value_from_piece(&self, p: Piece) -> i32 {
         match p {
-            Piece::Queen => QUEEN_VALUE,
-            Piece::Rook => ROOK_VALUE,
-            Piece::Bishop => BISHOP_VALUE,
-            Piece::Knight => KNIGHT_VALUE,
-            Piece::Pawn => PAWN_VALUE,
+            Piece::Queen => self.queen_value,
+            Piece::Rook => self.rook_value,
+            Piece::Bishop => self.bishop_value,
+            Piece::Knight => self.knight_value,
+            Piece::Pawn => self.pawn_value,
         }
     }
 
-    pub const fn threat_bounty(f: Field) -> i32 {
+    pub const fn threat_bounty(&self, f: Field) -> i32 {
         match f {
-            Field::Empty => EMPTY_THREATENED_VALUE,
+            Field::Empty => self.empty_threatened_value,
             Field::BlackPiece(p) | Field::WhitePiece(p) => match p {
-                Piece::Queen => QUEEN_THREATENED_VALUE,
-                Piece::Rook => ROOK_THREATENED_VALUE,
-                Piece::Bishop => BISHOP_THREATENED_VALUE,
-                Piece::Knight => KNIGHT_THREATENED_VALUE,
-                Piece::Pawn => PAWN_THREATENED_VALUE,
+                Piece::Queen => self.queen_threatened_value,
+                Piece::Rook => self.rook_threatened_value,
+                Piece::Bishop => self.bishop_threatened_value,
+                Piece::Knight => self.knight_threatened_value,
+                Piece::Pawn => self.pawn_threatened_value,
             },
-            _ => 0,
+            Field::BlackKing | Field::WhiteKing => self.king_threatened_value,
+            Field::Invincible => 0,
         }
     }
 
-    pub const fn border_penalty(p: Piece) -> i32 {
+    pub const fn border_penalty(&self, p: Piece) -> i32 {
         match p {
-            Piece::Queen => BORDER_QUEEN_PENALTY,
-            Piece::Rook => BORDER_ROOK_PENALTY,
-            Piece::Bishop => BORDER_BISHOP_PENALTY,
-            Piece::Knight => BORDER_KNIGHT_PENALTY,
-            Piece::Pawn => BORDER_PAWN_PENALTY,
+            Piece::Queen => self.border_queen_penalty,
+            Piece::Rook => self.border_rook_penalty,
+            Piece::Bishop => self.border_bishop_penalty,
+            Piece::Knight => self.border_knight_penalty,
+            Piece::Pawn => self.border_pawn_penalty,
+        }
+    }
+}
+
+/// The evaluation of one side split up into its terms
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct EvalTerms {
+    pub material: i32,
+    pub threat_bounty: i32,
+    pub centralization: i32,
+    pub border_penalty: i32,
+    /// The score of the rules of the variant, which belongs to no square
+    pub variant: i32,
+}
+
+impl EvalTerms {
+    pub const fn total(&self) -> i32 {
+        self.material
+            + self.threat_bounty
+            + self.centralization
+            + self.border_penalty
+            + self.variant
+    }
+}
+
+impl core::ops::AddAssign for EvalTerms {
+    fn add_assign(&mut self, o: Self) {
+        self.material += o.material;
+        self.threat_bounty += o.threat_bounty;
+        self.centralization += o.centralization;
+        self.border_penalty += o.border_penalty;
+        self.variant += o.variant;
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SquareTrace {
+    pub x: i8,
+    pub y: i8,
+    pub white: EvalTerms,
+    pub black: EvalTerms,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EvalTrace {
+    pub white: EvalTerms,
+    pub black: EvalTerms,
+    pub squares: Vec<SquareTrace>,
+    /// The score of a known endgame from white's perspective, which replaces the terms
+    pub endgame: Option<i32>,
+}
+
+impl EvalTrace {
+    /// The score from white's perspective, equal to `get_white_board_score`
+    pub const fn score(&self) -> i32 {
+        match self.endgame {
+            Some(score) => score,
+            None => self.white.total() - self.black.total(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Score {
+    MeWins,
+    EnemyWins,
+    /// A stalemate, which is rated below every value
+    Stalemate,
+    /// The negation of `Stalemate`, which is rated above every value
+    EnemyStalemate,
+    Value(i32),
+}
+
+impl Score {
+    pub const fn min() -> Self {
+        Self::EnemyWins
+    }
+
+    pub const fn max() -> Self {
+        Self::MeWins
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::MeWins => write!(f, "+mate"),
+            Self::EnemyWins => write!(f, "-mate"),
+            Self::Stalemate | Self::EnemyStalemate => write!(f, "stalemate"),
+            Self::Value(v) => write!(f, "{:+}", v),
         }
     }
 }
@@ -91,7 +288,8 @@ impl core::ops::Neg for Score {
         match self {
             Self::MeWins => Self::EnemyWins,
             Self::EnemyWins => Self::MeWins,
-            Self::Stalemate => Self::Stalemate,
+            Self::Stalemate => Self::EnemyStalemate,
+            Self::EnemyStalemate => Self::Stalemate,
             Self::Value(v) => Self::Value(-v),
         }
     }
@@ -107,7 +305,11 @@ impl Ord for Score {
             (Self::EnemyWins, _) => Ordering::Less,
             (Self::Stalemate, Self::EnemyWins) => Ordering::Greater,
             (Self::Stalemate, _) => Ordering::Less,
-            (Self::Value(_), Self::MeWins) => Ordering::Less,
+            (Self::EnemyStalemate, Self::MeWins) => Ordering::Less,
+            (Self::EnemyStalemate, _) => Ordering::Greater,
+            (Self::Value(_), Self::MeWins) | (Self::Value(_), Self::EnemyStalemate) => {
+                Ordering::Less
+            }
             (Self::Value(_), Self::Stalemate) | (Self::Value(_), Self::EnemyWins) => {
                 Ordering::Greater
             }
@@ -0,0 +1,104 @@
+use crate::decision::Config;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A named strength of the computer, which limits its search and lets it play inaccuracies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Skill {
+    Beginner,
+    Casual,
+    Intermediate,
+    Advanced,
+    Master,
+}
+
+pub const SKILLS: [Skill; 5] = [
+    Skill::Beginner,
+    Skill::Casual,
+    Skill::Intermediate,
+    Skill::Advanced,
+    Skill::Master,
+];
+
+#[derive(Debug, Clone)]
+pub struct SkillFromStrError(String);
+
+impl std::fmt::Display for SkillFromStrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown skill level '{}'", self.0)
+    }
+}
+
+impl std::error::Error for SkillFromStrError {}
+
+impl Skill {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Beginner => "beginner",
+            Self::Casual => "casual",
+            Self::Intermediate => "intermediate",
+            Self::Advanced => "advanced",
+            Self::Master => "master",
+        }
+    }
+
+    /// The maximum depth, the time per move and the score margin of the random move choice
+    const fn limits(&self) -> Option<(u32, Duration, i32)> {
+        Some(match self {
+            Self::Beginner => (1, Duration::from_millis(200), 3000),
+            Self::Casual => (2, Duration::from_millis(500), 1500),
+            Self::Intermediate => (3, Duration::from_secs(1), 600),
+            Self::Advanced => (4, Duration::from_secs(2), 200),
+            Self::Master => return None,
+        })
+    }
+
+    /// Weaken `config` to this level, the master plays with the unchanged configuration
+    pub fn apply(&self, config: &mut Config) {
+        if let Some((depth, move_time, random_margin)) = self.limits() {
+            config.depth = config.depth.min(depth);
+            config.move_time = Some(config.move_time.map_or(move_time, |t| t.min(move_time)));
+            config.random_margin = config.random_margin.max(random_margin);
+        }
+    }
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Self::Master
+    }
+}
+
+impl std::fmt::Display for Skill {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl core::str::FromStr for Skill {
+    type Err = SkillFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SKILLS
+            .iter()
+            .find(|skill| skill.name().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| SkillFromStrError(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        for skill in &SKILLS {
+            assert_eq!(skill.to_string().parse::<Skill>().ok(), Some(*skill));
+        }
+        assert_eq!(" Casual ".parse::<Skill>().ok(), Some(Skill::Casual));
+        assert_eq!("ADVANCED".parse::<Skill>().ok(), Some(Skill::Advanced));
+        assert!("grandmaster".parse::<Skill>().is_err());
+        assert!("".parse::<Skill>().is_err());
+        assert_eq!(Skill::default(), Skill::Master);
+    }
+}
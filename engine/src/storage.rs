@@ -0,0 +1,108 @@
+use crate::chessmatch::MatchResult;
+use crate::clock::TimeControl;
+use crate::variant::Variant;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// The match could not be replayed from its record
+    InvalidRecord(u32),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "could not access the match storage: {}", e),
+            Self::Json(e) => write!(f, "invalid json match file: {}", e),
+            Self::InvalidRecord(id) => write!(f, "match {} cannot be replayed", id),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// The clock of a stored match, which continues with these times after loading
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockRecord {
+    pub control: TimeControl,
+    pub white: Duration,
+    pub black: Duration,
+}
+
+/// Everything needed to restore a match, the positions are replayed from the moves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRecord<E> {
+    /// The starting position in FEN
+    pub start: String,
+    pub variant: Variant,
+    /// The moves in standard algebraic notation
+    pub moves: Vec<String>,
+    pub result: Option<MatchResult>,
+    pub clock: Option<ClockRecord>,
+    pub extra: E,
+}
+
+/// The stored matches with their ids, each of which may have failed to load
+pub type LoadedRecords<E> = Vec<(u32, Result<MatchRecord<E>, StorageError>)>;
+
+/// Gets the errors of loading and writing the match with the id, which the registry can only
+/// report since they do not stop the match
+pub type StorageErrorHandler = Arc<dyn Fn(u32, StorageError) + Send + Sync>;
+
+/// Where a `MatchRegistry` keeps its matches across restarts
+pub trait Storage<E>: Send + Sync {
+    /// Write the match `id`, replacing an older record of it
+    fn save(&self, id: u32, record: &MatchRecord<E>) -> Result<(), StorageError>;
+    /// Read all stored matches with their ids in ascending order, a record that cannot be
+    /// read does not prevent loading the others
+    fn load_all(&self) -> Result<LoadedRecords<E>, StorageError>;
+}
+
+/// Stores every match as a JSON file named after its id in a directory
+pub struct JsonFileStorage {
+    dir: PathBuf,
+}
+
+impl JsonFileStorage {
+    /// Use the directory `dir`, which is created if it does not exist
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Self, StorageError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(StorageError::Io)?;
+        Ok(Self { dir })
+    }
+}
+
+impl<E: Serialize + DeserializeOwned> Storage<E> for JsonFileStorage {
+    fn save(&self, id: u32, record: &MatchRecord<E>) -> Result<(), StorageError> {
+        let json = serde_json::to_string_pretty(record).map_err(StorageError::Json)?;
+        // a crash while writing must not destroy the previous record
+        let tmp = self.dir.join(format!("{}.json.tmp", id));
+        std::fs::write(&tmp, json).map_err(StorageError::Io)?;
+        std::fs::rename(&tmp, self.dir.join(format!("{}.json", id))).map_err(StorageError::Io)
+    }
+
+    fn load_all(&self) -> Result<LoadedRecords<E>, StorageError> {
+        let mut records = vec![];
+        for entry in std::fs::read_dir(&self.dir).map_err(StorageError::Io)? {
+            let path = entry.map_err(StorageError::Io)?.path();
+            let id = match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => path.file_stem().and_then(|s| s.to_str()?.parse().ok()),
+                _ => None,
+            };
+            if let Some(id) = id {
+                let record = std::fs::read_to_string(&path)
+                    .map_err(StorageError::Io)
+                    .and_then(|s| serde_json::from_str(&s).map_err(StorageError::Json));
+                records.push((id, record));
+            }
+        }
+        records.sort_by_key(|&(id, _)| id);
+        Ok(records)
+    }
+}
@@ -0,0 +1,1367 @@
+//! Probing of Syzygy endgame tablebases
+//!
+//! The WDL tables (`.rtbw`) store whether a position is won, drawn or lost, the DTZ tables
+//! (`.rtbz`) store the distance to the next capture or pawn move that keeps the result.
+//! The file format and the index computation follow the reference prober by Ronald de Man.
+
+use crate::board::{Board, Color, Coord, Field, Piece};
+use crate::moves::{LongMoveList, Move};
+use crate::variant::Variant;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+/// The maximum number of pieces including the kings of any Syzygy table
+const MAX_PIECES: usize = 7;
+
+// flags of the compressed tables
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+#[derive(Debug)]
+pub enum TablebaseError {
+    Io(std::io::Error),
+    /// The directory does not contain any table files
+    NoTables(PathBuf),
+}
+
+impl std::fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::NoTables(p) => write!(f, "no syzygy tables found in '{}'", p.display()),
+        }
+    }
+}
+
+impl std::error::Error for TablebaseError {}
+
+/// The result of a position for the side to move, cursed wins and blessed losses are
+/// only drawn because of the fifty move rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(v: i32) -> Self {
+        match v {
+            i32::MIN..=-2 => Self::Loss,
+            -1 => Self::BlessedLoss,
+            0 => Self::Draw,
+            1 => Self::CursedWin,
+            _ => Self::Win,
+        }
+    }
+
+    pub(crate) fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+
+    /// The DTZ of a position, whose best move is a capture or a pawn move
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Self::Win => 1,
+            Self::CursedWin => 101,
+            Self::Draw => 0,
+            Self::BlessedLoss => -101,
+            Self::Loss => -1,
+        }
+    }
+}
+
+impl core::ops::Neg for Wdl {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::from_value(-(self as i32))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Wdl => "rtbw",
+            Self::Dtz => "rtbz",
+        }
+    }
+}
+
+/// A position as the tables see it: squares from 0 (a1) to 63 (h8) in ascending order and
+/// piece codes 1 to 6 for the white pawn to king and 9 to 14 for the black ones
+struct Position {
+    pieces: Vec<(usize, u8)>,
+    black_to_move: bool,
+}
+
+impl Position {
+    fn new(board: &Board, color: Color) -> Self {
+        let mut pieces = vec![];
+        for y in 0..8 {
+            for x in 0..8 {
+                let code = match *board.get(Coord::from_xy(x, y)) {
+                    Field::WhitePiece(p) => piece_code(p),
+                    Field::WhiteKing => 6,
+                    Field::BlackPiece(p) => piece_code(p) + 8,
+                    Field::BlackKing => 14,
+                    _ => continue,
+                };
+                pieces.push((y as usize * 8 + x as usize, code));
+            }
+        }
+        Self {
+            pieces,
+            black_to_move: color == Color::Black,
+        }
+    }
+
+    /// The pieces of one side in the notation of the file names e.g. `KRP`
+    fn material(&self, black: bool) -> String {
+        let mut codes: Vec<u8> = self
+            .pieces
+            .iter()
+            .map(|&(_, p)| p)
+            .filter(|&p| (p >= 8) == black)
+            .map(|p| p & 7)
+            .collect();
+        codes.sort_unstable_by(|a, b| b.cmp(a));
+        codes
+            .into_iter()
+            .map(|p| b" PNBRQK"[p as usize] as char)
+            .collect()
+    }
+}
+
+const fn piece_code(p: Piece) -> u8 {
+    match p {
+        Piece::Pawn => 1,
+        Piece::Knight => 2,
+        Piece::Bishop => 3,
+        Piece::Rook => 4,
+        Piece::Queen => 5,
+    }
+}
+
+const fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+/// Index tables that are shared by every table
+struct Maps {
+    binomial: [[u64; 64]; MAX_PIECES],
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+}
+
+impl Maps {
+    fn new() -> Box<Self> {
+        let mut maps = Box::new(Self {
+            binomial: [[0; 64]; MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        });
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                maps.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+        // the a1-d1-d4 triangle, the squares on the diagonal come last
+        let mut diagonal = vec![];
+        code = 0;
+        for sq in 0..28 {
+            if off_diagonal(sq) < 0 && sq % 8 <= 3 {
+                maps.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && sq % 8 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            maps.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+        // the 462 placements of two kings with the first one in the triangle
+        let mut both_on_diagonal = vec![];
+        let mut code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if maps.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let dx = (s1 % 8) as i32 - (s2 % 8) as i32;
+                    let dy = (s1 / 8) as i32 - (s2 / 8) as i32;
+                    if dx.abs().max(dy.abs()) <= 1
+                        || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0)
+                    {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        maps.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            maps.map_kk[idx][s2] = code;
+            code += 1;
+        }
+        for n in 0..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                maps.binomial[k][n] = match (k, n) {
+                    (0, _) => 1,
+                    (_, 0) => 0,
+                    _ => {
+                        maps.binomial[k - 1][n - 1]
+                            + if k < n { maps.binomial[k][n - 1] } else { 0 }
+                    }
+                };
+            }
+        }
+        // the leading pawn is the one nearest to the edge and among those the lowest one
+        let mut available = 47;
+        for lead in 1..MAX_PIECES - 1 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead == 1 {
+                        maps.map_pawns[sq] = available;
+                        maps.map_pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    maps.lead_pawn_idx[lead][sq] = idx;
+                    idx += maps.binomial[lead - 1][maps.map_pawns[sq]];
+                }
+                maps.lead_pawns_size[lead][file] = idx;
+            }
+        }
+        maps
+    }
+}
+
+/// The material of a table given by its file name e.g. `KRPvKR`
+#[derive(Clone, Debug)]
+struct Material {
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    /// Both sides have the same pieces
+    symmetric: bool,
+    /// The pawns of the leading side, which has fewer pawns, and of the other side
+    pawn_count: [usize; 2],
+}
+
+impl Material {
+    fn new(name: &str) -> Option<Self> {
+        let mut sides = name.split('v');
+        let (white, black) = (sides.next()?, sides.next()?);
+        if sides.next().is_some() || !white.starts_with('K') || !black.starts_with('K') {
+            return None;
+        }
+        let count = |side: &str, c| side.chars().filter(|&p| p == c).count();
+        let pawns = [count(white, 'P'), count(black, 'P')];
+        let lead_white = pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]);
+        Some(Self {
+            piece_count: white.len() + black.len(),
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces: [white, black]
+                .iter()
+                .any(|side| "QRBNP".chars().any(|c| count(side, c) == 1)),
+            symmetric: white == black,
+            pawn_count: if lead_white {
+                pawns
+            } else {
+                [pawns[1], pawns[0]]
+            },
+        })
+    }
+}
+
+/// The decoding information of one subtable, there is one for every side to move and for
+/// every file of the leading pawn
+#[derive(Clone, Debug, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: usize,
+    /// The offsets into the file of the lowest symbols, the symbol tree, the sparse index,
+    /// the block lengths and the compressed data
+    lowest_sym: usize,
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_length: usize,
+    block_length_size: usize,
+    data: usize,
+    /// The lowest symbol of every length left aligned to 64 bits
+    base64: Vec<u64>,
+    /// The number of values minus one that a symbol expands to
+    symlen: Vec<u8>,
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    /// Offsets into the DTZ map for a win, a loss, a cursed win and a blessed loss
+    map_idx: [usize; 4],
+}
+
+/// A loaded table file
+struct Table {
+    data: Vec<u8>,
+    kind: TableKind,
+    material: Material,
+    /// The subtables indexed by the side to move and the file of the leading pawn
+    pairs: Vec<Vec<PairsData>>,
+    dtz_map: usize,
+}
+
+enum TableValue {
+    Value(i32),
+    /// The DTZ table only stores the positions with the other side to move
+    OtherSide,
+}
+
+impl Table {
+    fn load(path: &Path, kind: TableKind, material: Material, maps: &Maps) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let magic = match kind {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        };
+        if data.get(..4)? != magic {
+            return None;
+        }
+        let mut table = Self {
+            data,
+            kind,
+            material,
+            pairs: vec![],
+            dtz_map: 0,
+        };
+        table.init(maps)?;
+        Some(table)
+    }
+
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    fn u16_le(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes([
+            self.byte(offset)?,
+            self.byte(offset + 1)?,
+        ]))
+    }
+
+    fn u32_le(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes([
+            self.byte(offset)?,
+            self.byte(offset + 1)?,
+            self.byte(offset + 2)?,
+            self.byte(offset + 3)?,
+        ]))
+    }
+
+    fn u32_be(&self, offset: usize) -> Option<u32> {
+        self.u32_le(offset).map(u32::swap_bytes)
+    }
+
+    /// The left and the right child of a symbol, which are stored in 12 bits each
+    fn children(&self, d: &PairsData, sym: usize) -> Option<(usize, usize)> {
+        let offset = d.btree + 3 * sym;
+        let (b0, b1, b2) = (
+            self.byte(offset)? as usize,
+            self.byte(offset + 1)? as usize,
+            self.byte(offset + 2)? as usize,
+        );
+        Some((((b1 & 0xf) << 8) | b0, (b2 << 4) | (b1 >> 4)))
+    }
+
+    /// Read the header of the file
+    fn init(&mut self, maps: &Maps) -> Option<()> {
+        let m = self.material.clone();
+        let flags = self.byte(4)?;
+        if (flags & 2 != 0) != m.has_pawns || (flags & 1 != 0) == m.symmetric {
+            return None;
+        }
+        let sides = match self.kind {
+            TableKind::Wdl if !m.symmetric => 2,
+            _ => 1,
+        };
+        let files = if m.has_pawns { 4 } else { 1 };
+        let pp = m.has_pawns && m.pawn_count[1] > 0;
+        let mut pairs = vec![vec![PairsData::default(); files]; sides];
+        let mut p = 5;
+        for f in 0..files {
+            let (o0, o1) = (self.byte(p)?, if pp { self.byte(p + 1)? } else { 0xff });
+            let order = [[o0 & 0xf, o1 & 0xf], [o0 >> 4, o1 >> 4]];
+            p += 1 + pp as usize;
+            for k in 0..m.piece_count {
+                let b = self.byte(p)?;
+                for (i, side) in pairs.iter_mut().enumerate() {
+                    side[f].pieces[k] = if i == 0 { b & 0xf } else { b >> 4 };
+                }
+                p += 1;
+            }
+            for (i, side) in pairs.iter_mut().enumerate() {
+                side[f].set_groups(&m, order[i], f, maps)?;
+            }
+        }
+        p += p & 1;
+        for f in 0..files {
+            for side in pairs.iter_mut() {
+                p = self.set_sizes(&mut side[f], p)?;
+            }
+        }
+        if self.kind == TableKind::Dtz {
+            self.dtz_map = p;
+            for d in pairs[0].iter_mut() {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & FLAG_WIDE != 0 {
+                    p += p & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (p - self.dtz_map) / 2 + 1;
+                        p += 2 * self.u16_le(p)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = p - self.dtz_map + 1;
+                        p += self.byte(p)? as usize + 1;
+                    }
+                }
+            }
+            p += p & 1;
+        }
+        for f in 0..files {
+            for side in pairs.iter_mut() {
+                side[f].sparse_index = p;
+                p += side[f].sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for side in pairs.iter_mut() {
+                side[f].block_length = p;
+                p += side[f].block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for side in pairs.iter_mut() {
+                p = (p + 0x3f) & !0x3f;
+                side[f].data = p;
+                p += side[f].num_blocks * side[f].block_size;
+            }
+        }
+        if p > self.data.len() {
+            return None;
+        }
+        self.pairs = pairs;
+        Some(())
+    }
+
+    /// Read the Huffman code of a subtable and return the offset behind it
+    fn set_sizes(&self, d: &mut PairsData, mut p: usize) -> Option<usize> {
+        d.flags = self.byte(p)?;
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            // the only value is stored as the minimal symbol length
+            d.min_sym_len = self.byte(p + 1)? as usize;
+            return Some(p + 2);
+        }
+        let groups = d.group_len.iter().position(|&l| l == 0)?;
+        let size = d.group_idx[groups];
+        d.block_size = 1 << self.byte(p + 1)?;
+        d.span = 1 << self.byte(p + 2)?;
+        d.sparse_index_size = ((size + d.span - 1) / d.span) as usize;
+        let padding = self.byte(p + 3)? as usize;
+        d.num_blocks = self.u32_le(p + 4)? as usize;
+        d.block_length_size = d.num_blocks + padding;
+        let max_sym_len = self.byte(p + 8)? as usize;
+        d.min_sym_len = self.byte(p + 9)? as usize;
+        if max_sym_len < d.min_sym_len || d.min_sym_len == 0 {
+            return None;
+        }
+        p += 10;
+        d.lowest_sym = p;
+        let lengths = max_sym_len - d.min_sym_len + 1;
+        let lowest = |i| self.u16_le(p + 2 * i).map(u64::from);
+        // canonical Huffman codes: longer symbols have lower values
+        d.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            d.base64[i] = d.base64[i + 1]
+                .wrapping_add(lowest(i)?)
+                .wrapping_sub(lowest(i + 1)?)
+                / 2;
+        }
+        for (i, base) in d.base64.iter_mut().enumerate() {
+            let shift = 64usize.checked_sub(i + d.min_sym_len)?;
+            *base = base.checked_shl(shift as u32).unwrap_or(0);
+        }
+        p += 2 * lengths;
+        let symbols = self.u16_le(p)? as usize;
+        p += 2;
+        d.btree = p;
+        d.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                d.symlen[sym] = self.symlen(d, sym, &mut visited)?;
+            }
+        }
+        Some(p + 3 * symbols + (symbols & 1))
+    }
+
+    /// The number of values minus one that a symbol of the recursive pairing expands to
+    fn symlen(&self, d: &mut PairsData, sym: usize, visited: &mut [bool]) -> Option<u8> {
+        visited[sym] = true;
+        let (left, right) = self.children(d, sym)?;
+        if right == 0xfff {
+            return Some(0);
+        }
+        for &child in &[left, right] {
+            if !*visited.get(child)? {
+                d.symlen[child] = self.symlen(d, child, visited)?;
+            }
+        }
+        Some(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let side = &self.pairs[stm % self.pairs.len()];
+        &side[file.min(side.len() - 1)]
+    }
+
+    /// Decode the value at `idx` of a subtable
+    fn decompress(&self, d: &PairsData, idx: u64) -> Option<u16> {
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(d.min_sym_len as u16);
+        }
+        // the sparse index points to the value in the middle of every span
+        let entry = d.sparse_index + 6 * (idx / d.span) as usize;
+        let mut block = self.u32_le(entry)? as usize;
+        let mut offset = self.u16_le(entry + 4)? as i64;
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+        let block_length = |block| self.u16_le(d.block_length + 2 * block).map(i64::from);
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf = (self.u32_be(ptr)? as u64) << 32 | self.u32_be(ptr + 4)? as u64;
+        ptr += 8;
+        let mut buf_size: usize = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < *d.base64.get(len)? {
+                len += 1;
+            }
+            let shift = 64usize.checked_sub(len + d.min_sym_len)?;
+            sym = (buf - d.base64[len]).checked_shr(shift as u32).unwrap_or(0) as usize;
+            sym += self.u16_le(d.lowest_sym + 2 * len)? as usize;
+            let count = *d.symlen.get(sym)? as i64 + 1;
+            if offset < count {
+                break;
+            }
+            offset -= count;
+            let len = len + d.min_sym_len;
+            buf = buf.checked_shl(len as u32).unwrap_or(0);
+            buf_size = buf_size.checked_sub(len)?;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (self.u32_be(ptr)? as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+        // descend the pairs until reaching a single value
+        while d.symlen[sym] != 0 {
+            let (left, right) = self.children(d, sym)?;
+            let count = *d.symlen.get(left)? as i64 + 1;
+            if offset < count {
+                sym = left;
+            } else {
+                offset -= count;
+                sym = right;
+            }
+        }
+        self.children(d, sym).map(|(value, _)| value as u16)
+    }
+
+    /// Convert a stored DTZ value to plies
+    fn map_dtz(&self, file: usize, value: u16, wdl: Wdl) -> Option<i32> {
+        let d = self.get(0, file);
+        let mut value = value as usize;
+        if d.flags & FLAG_MAPPED != 0 {
+            let idx = d.map_idx[match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            }];
+            value = if d.flags & FLAG_WIDE != 0 {
+                self.u16_le(self.dtz_map + 2 * (idx + value))? as usize
+            } else {
+                self.byte(self.dtz_map + idx + value)? as usize
+            };
+        }
+        let plies = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES != 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES != 0,
+            _ => false,
+        };
+        Some(if plies { value } else { 2 * value } as i32 + 1)
+    }
+
+    /// Look up the value of the position, `flip` swaps the colors because the stronger side
+    /// is always stored as white
+    fn probe(&self, pos: &Position, flip: bool, wdl: Wdl, maps: &Maps) -> Option<TableValue> {
+        let (stm, file, idx) = self.index(pos, flip, maps)?;
+        let d = self.get(stm, file);
+        let m = &self.material;
+        if self.kind == TableKind::Dtz
+            && (d.flags & FLAG_STM) as usize != stm
+            && (!m.symmetric || m.has_pawns)
+        {
+            return Some(TableValue::OtherSide);
+        }
+        let value = self.decompress(d, idx)?;
+        Some(TableValue::Value(match self.kind {
+            TableKind::Wdl => value as i32 - 2,
+            TableKind::Dtz => self.map_dtz(file, value, wdl)?,
+        }))
+    }
+
+    /// The side to move and the file of the leading pawn, which select the subtable, and
+    /// the index of the position in it
+    fn index(&self, pos: &Position, flip: bool, maps: &Maps) -> Option<(usize, usize, u64)> {
+        let m = &self.material;
+        let flip = flip || (m.symmetric && pos.black_to_move);
+        let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+        let stm = (flip ^ pos.black_to_move) as usize;
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let (mut size, mut lead, mut file) = (0, 0, 0);
+        let mut lead_pawn = None;
+        if m.has_pawns {
+            let pawn = self.get(0, 0).pieces[0] ^ flip_color;
+            lead_pawn = Some(pawn);
+            for &(sq, _) in pos.pieces.iter().filter(|&&(_, p)| p == pawn) {
+                squares[size] = sq ^ flip_squares;
+                size += 1;
+            }
+            lead = size;
+            let front = (0..lead).max_by_key(|&i| maps.map_pawns[squares[i]])?;
+            squares.swap(0, front);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+        for &(sq, p) in pos.pieces.iter().filter(|&&(_, p)| Some(p) != lead_pawn) {
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = p ^ flip_color;
+            size += 1;
+        }
+        if size != m.piece_count {
+            return None;
+        }
+        let d = self.get(stm, file);
+        // order the pieces like the table
+        for i in lead..size - 1 {
+            if let Some(j) = (i + 1..size).find(|&j| d.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+        if squares[0] % 8 > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+        let mut idx;
+        if m.has_pawns {
+            idx = maps.lead_pawn_idx[lead][squares[0]];
+            squares[1..lead].sort_by_key(|&sq| maps.map_pawns[sq]);
+            for (i, &sq) in squares[1..lead].iter().enumerate() {
+                idx += maps.binomial[i + 1][maps.map_pawns[sq]];
+            }
+        } else {
+            if squares[0] / 8 > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+            // mirror at the a1-h8 diagonal if the first piece off it is above
+            if let Some(i) = (0..d.group_len[0]).find(|&i| off_diagonal(squares[i]) != 0) {
+                if off_diagonal(squares[i]) > 0 {
+                    for sq in &mut squares[i..size] {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+            }
+            idx = if m.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                (if off_diagonal(s0) != 0 {
+                    (maps.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + (s0 / 8) * 28 + maps.map_b1h1h7[s1]) * 62 + s2 - adjust2
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + (s0 / 8) * 7 * 28
+                        + (s1 / 8 - adjust1) * 28
+                        + maps.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + (s0 / 8) * 7 * 6
+                        + (s1 / 8 - adjust1) * 6
+                        + (s2 / 8 - adjust2)
+                }) as u64
+            } else {
+                maps.map_kk[maps.map_a1d1d4[squares[0]]][squares[1]]
+            };
+        }
+        idx *= d.group_idx[0];
+        // the remaining groups are encoded by their squares without the occupied ones
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = m.has_pawns && m.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                let pawn_rank = if remaining_pawns { 8 } else { 0 };
+                n += maps.binomial[i + 1][sq.checked_sub(adjust + pawn_rank)?];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+        Some((stm, file, idx))
+    }
+}
+
+impl PairsData {
+    /// Split the pieces into the groups that are encoded together and compute the factor
+    /// of every group
+    fn set_groups(&mut self, m: &Material, order: [u8; 2], file: usize, maps: &Maps) -> Option<()> {
+        let mut first_len: i32 = if m.has_pawns {
+            0
+        } else if m.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..m.piece_count {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+        let pp = m.has_pawns && m.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - self.group_len[0] - if pp { self.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                self.group_idx[0] = idx;
+                idx *= if m.has_pawns {
+                    *maps.lead_pawns_size.get(self.group_len[0])?.get(file)?
+                } else if m.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                self.group_idx[1] = idx;
+                idx *= maps.binomial.get(self.group_len[1])?[48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= maps.binomial.get(self.group_len[next])?[free_squares];
+                free_squares = free_squares.checked_sub(self.group_len[next])?;
+                next += 1;
+            }
+            k = k.checked_add(1)?;
+        }
+        self.group_idx[n] = idx;
+        Some(())
+    }
+}
+
+type TableId = (String, TableKind);
+
+/// A directory of Syzygy tables, which are loaded on their first probe
+pub struct Tablebase {
+    dir: PathBuf,
+    /// The names of the available tables e.g. `KRvK`
+    available: HashSet<TableId>,
+    /// The tables that were probed so far, `None` if the file could not be read. Once a table
+    /// is loaded, probing it only takes the lock for reading.
+    loaded: RwLock<HashMap<TableId, Option<Arc<Table>>>>,
+    max_pieces: usize,
+    maps: Box<Maps>,
+}
+
+impl std::fmt::Debug for Tablebase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Tablebase")
+            .field("dir", &self.dir)
+            .field("tables", &self.available.len())
+            .field("max_pieces", &self.max_pieces)
+            .finish()
+    }
+}
+
+fn is_pawn_move(board: &Board, mv: &Move) -> bool {
+    matches!(
+        board.get(mv.start),
+        Field::WhitePiece(Piece::Pawn) | Field::BlackPiece(Piece::Pawn)
+    )
+}
+
+fn legal_moves(board: &Board, color: Color) -> Vec<Move> {
+    let mut moves = LongMoveList::new();
+    board.enumerate_all_moves_by(color, &mut moves);
+    moves.slice().to_vec()
+}
+
+fn after_move(board: &Board, mv: Move, color: Color) -> Board {
+    let mut board = board.clone();
+    board.do_move(mv);
+    board.update_aggressors(!color);
+    board
+}
+
+impl Tablebase {
+    /// Find the table files in `dir`, their contents are read on demand
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, TablebaseError> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut available = HashSet::new();
+        for entry in std::fs::read_dir(&dir).map_err(TablebaseError::Io)? {
+            let path = entry.map_err(TablebaseError::Io)?.path();
+            let kind = match path.extension().and_then(|e| e.to_str()) {
+                Some("rtbw") => TableKind::Wdl,
+                Some("rtbz") => TableKind::Dtz,
+                _ => continue,
+            };
+            if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                if matches!(Material::new(name), Some(m) if m.piece_count <= MAX_PIECES) {
+                    available.insert((name.to_string(), kind));
+                }
+            }
+        }
+        let max_pieces = available
+            .iter()
+            .map(|(name, _)| name.len() - 1)
+            .max()
+            .ok_or_else(|| TablebaseError::NoTables(dir.clone()))?;
+        Ok(Self {
+            dir,
+            available,
+            loaded: RwLock::new(HashMap::new()),
+            max_pieces,
+            maps: Maps::new(),
+        })
+    }
+
+    /// The maximum number of pieces including the kings of the available tables
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether the position might be in the tables, positions with castling rights or of
+    /// another variant never are
+    pub fn covers(&self, board: &Board) -> bool {
+        if board.variant() != Variant::Standard {
+            return false;
+        }
+        let mut count = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                if !matches!(*board.get(Coord::from_xy(x, y)), Field::Empty) {
+                    count += 1;
+                }
+            }
+        }
+        count <= self.max_pieces && !board.castling_rights().iter().any(|&r| r)
+    }
+
+    /// The table of the material and whether the colors are swapped in it
+    fn table(&self, pos: &Position, kind: TableKind) -> Option<(Arc<Table>, bool)> {
+        let (white, black) = (pos.material(false), pos.material(true));
+        let (name, flip) = [
+            (format!("{}v{}", white, black), false),
+            (format!("{}v{}", black, white), true),
+        ]
+        .iter()
+        .find(|(name, _)| self.available.contains(&(name.clone(), kind)))
+        .cloned()?;
+        let id = (name.clone(), kind);
+        if let Some(table) = self.loaded.read().unwrap().get(&id) {
+            return Some((table.clone()?, flip));
+        }
+        let mut loaded = self.loaded.write().unwrap();
+        let table = loaded
+            .entry(id)
+            .or_insert_with(|| {
+                let path = self.dir.join(format!("{}.{}", name, kind.extension()));
+                // a missing or corrupted file is treated like an unknown position
+                Table::load(&path, kind, Material::new(&name)?, &self.maps).map(Arc::new)
+            })
+            .clone()?;
+        Some((table, flip))
+    }
+
+    fn probe_wdl_table(&self, board: &Board, color: Color) -> Option<Wdl> {
+        let pos = Position::new(board, color);
+        if pos.pieces.len() == 2 {
+            return Some(Wdl::Draw);
+        }
+        let (table, flip) = self.table(&pos, TableKind::Wdl)?;
+        match table.probe(&pos, flip, Wdl::Draw, &self.maps)? {
+            TableValue::Value(v) => Some(Wdl::from_value(v)),
+            TableValue::OtherSide => None,
+        }
+    }
+
+    /// Search the captures (and pawn moves if `zeroing`) because the tables store arbitrary
+    /// values for positions where they win, return the result and whether the best move
+    /// is one of them
+    fn search(&self, board: &Board, color: Color, zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = legal_moves(board, color);
+        let mut best = Wdl::Loss;
+        let mut count = 0;
+        for &mv in &moves {
+            if !mv.is_capture() && (!zeroing || !is_pawn_move(board, &mv)) {
+                continue;
+            }
+            count += 1;
+            let (value, _) = self.search(&after_move(board, mv, color), !color, false)?;
+            if -value > best {
+                best = -value;
+                if best >= Wdl::Win {
+                    return Some((best, true));
+                }
+            }
+        }
+        let no_more_moves = count > 0 && count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            self.probe_wdl_table(board, color)?
+        };
+        if best >= value {
+            Some((best, best > Wdl::Draw || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    /// The result of the position for `color`, which is to move
+    pub fn probe_wdl(&self, board: &Board, color: Color) -> Option<Wdl> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.search(board, color, false).map(|(wdl, _)| wdl)
+    }
+
+    /// The number of plies until the next capture or pawn move for the side to move that
+    /// keeps the result, positive if the position is won and negative if it is lost
+    pub fn probe_dtz(&self, board: &Board, color: Color) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+        let (wdl, zeroing) = self.search(board, color, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(wdl.dtz_before_zeroing());
+        }
+        let pos = Position::new(board, color);
+        let (table, flip) = self.table(&pos, TableKind::Dtz)?;
+        if let TableValue::Value(dtz) = table.probe(&pos, flip, wdl, &self.maps)? {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+        }
+        // the table stores the other side to move, so look one ply ahead
+        let mut min = None;
+        for mv in legal_moves(board, color) {
+            let zeroing = mv.is_capture() || is_pawn_move(board, &mv);
+            let child = after_move(board, mv, color);
+            let mut dtz = if zeroing {
+                -self.search(&child, !color, false)?.0.dtz_before_zeroing()
+            } else {
+                -self.probe_dtz(&child, !color)?
+            };
+            // a mate is one ply away
+            if dtz == 1 && child.is_in_check(!color) && legal_moves(&child, !color).is_empty() {
+                min = Some(1);
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz.signum() == wdl.signum() && min.map_or_else(|| true, |m| dtz < m) {
+                min = Some(dtz);
+            }
+        }
+        Some(min.unwrap_or(-1))
+    }
+
+    /// The move that keeps the best result and reaches it the fastest or delays a loss
+    /// the longest together with the result
+    pub fn best_move(&self, board: &Board, color: Color) -> Option<(Move, Wdl)> {
+        if !self.covers(board) {
+            return None;
+        }
+        let mut best: Option<(Move, Wdl, i32)> = None;
+        for mv in legal_moves(board, color) {
+            let child = after_move(board, mv, color);
+            let (wdl, dtz) = if legal_moves(&child, !color).is_empty() {
+                if child.is_in_check(!color) {
+                    (Wdl::Win, 1)
+                } else {
+                    (Wdl::Draw, 0)
+                }
+            } else {
+                let wdl = -self.probe_wdl(&child, !color)?;
+                let dtz = if mv.is_capture() || is_pawn_move(board, &mv) {
+                    wdl.dtz_before_zeroing()
+                } else {
+                    let dtz = -self.probe_dtz(&child, !color)?;
+                    dtz + dtz.signum()
+                };
+                (wdl, dtz)
+            };
+            // wins are ranked by the shortest and losses by the longest distance
+            if best.map_or_else(|| true, |(_, w, d)| (wdl, -dtz) > (w, -d)) {
+                best = Some((mv, wdl, dtz));
+            }
+        }
+        best.map(|(mv, wdl, _)| (mv, wdl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table of `name` without data, which is enough to compute indices
+    fn table(name: &str, pieces: &[u8], maps: &Maps) -> Table {
+        let material = Material::new(name).unwrap();
+        let mut d = PairsData::default();
+        d.pieces[..pieces.len()].copy_from_slice(pieces);
+        d.set_groups(&material, [0, 0xf], 0, maps).unwrap();
+        Table {
+            data: vec![],
+            kind: TableKind::Wdl,
+            material,
+            pairs: vec![vec![d]; 2],
+            dtz_map: 0,
+        }
+    }
+
+    fn position(pieces: &[(usize, u8)], black_to_move: bool) -> Position {
+        let mut pieces = pieces.to_vec();
+        pieces.sort_unstable();
+        Position {
+            pieces,
+            black_to_move,
+        }
+    }
+
+    fn adjacent(a: usize, b: usize) -> bool {
+        ((a % 8) as i32 - (b % 8) as i32).abs() <= 1 && ((a / 8) as i32 - (b / 8) as i32).abs() <= 1
+    }
+
+    /// Every position gets an index below the size of the table, positions with different
+    /// indices are different and mirroring the board or the colors keeps the index
+    fn assert_index(table: &Table, size: u64, positions: &[Vec<(usize, u8)>], maps: &Maps) {
+        let d = table.get(0, 0);
+        let groups = d.group_len.iter().position(|&l| l == 0).unwrap();
+        assert_eq!(d.group_idx[groups], size);
+        let mut seen = HashMap::new();
+        for pieces in positions {
+            let (_, _, idx) = table.index(&position(pieces, false), false, maps).unwrap();
+            assert!(idx < size);
+            let mirrored: Vec<_> = pieces.iter().map(|&(sq, p)| (sq ^ 7, p)).collect();
+            let flipped: Vec<_> = pieces.iter().map(|&(sq, p)| (sq ^ 56, p ^ 8)).collect();
+            assert_eq!(
+                table.index(&position(&mirrored, false), false, maps),
+                Some((0, 0, idx))
+            );
+            assert_eq!(
+                table.index(&position(&flipped, true), true, maps),
+                Some((0, 0, idx))
+            );
+            // positions mirrored at the board's center or its diagonal share the index
+            let mut class: Vec<Vec<(usize, u8)>> = (0..8)
+                .map(|t| {
+                    let mut image: Vec<_> = pieces
+                        .iter()
+                        .map(|&(sq, p)| {
+                            let sq = if t & 1 != 0 { sq ^ 7 } else { sq };
+                            let sq = if t & 2 != 0 { sq ^ 56 } else { sq };
+                            let sq = if t & 4 != 0 {
+                                ((sq >> 3) | (sq << 3)) & 63
+                            } else {
+                                sq
+                            };
+                            (sq, p)
+                        })
+                        .collect();
+                    image.sort_unstable();
+                    image
+                })
+                .collect();
+            class.sort_unstable();
+            let previous = seen.entry(idx).or_insert_with(|| class.clone());
+            assert_eq!(*previous, class, "index {} is shared", idx);
+        }
+    }
+
+    #[test]
+    fn index_unique_pieces() {
+        let maps = Maps::new();
+        let table = table("KQvK", &[6, 5, 14], &maps);
+        let mut positions = vec![];
+        for k in 0..64 {
+            for q in (0..64).filter(|&q| q != k) {
+                for bk in (0..64).filter(|&bk| bk != k && bk != q && !adjacent(k, bk)) {
+                    positions.push(vec![(k, 6), (q, 5), (bk, 14)]);
+                }
+            }
+        }
+        assert_index(&table, 31332, &positions, &maps);
+    }
+
+    #[test]
+    fn index_kings_and_pair() {
+        let maps = Maps::new();
+        let table = table("KRRvK", &[6, 14, 4, 4], &maps);
+        let mut positions = vec![];
+        for k in 0..64 {
+            for bk in (0..64).filter(|&bk| bk != k && !adjacent(k, bk)) {
+                for r1 in [0, 9, 27, 42, 63].iter().filter(|&&r| r != k && r != bk) {
+                    for r2 in (r1 + 1..64).filter(|&r| r != k && r != bk) {
+                        positions.push(vec![(k, 6), (bk, 14), (*r1, 4), (r2, 4)]);
+                    }
+                }
+            }
+        }
+        assert_index(&table, 462 * 1891, &positions, &maps);
+    }
+
+    #[test]
+    fn maps() {
+        let maps = Maps::new();
+        assert_eq!(maps.binomial[2][5], 10);
+        assert_eq!(maps.binomial[3][62], 37820);
+        assert_eq!(maps.map_kk.iter().flatten().max(), Some(&461));
+        // the pawns on the a and h file come first
+        assert_eq!((maps.map_pawns[8], maps.map_pawns[15]), (47, 46));
+        assert_eq!(maps.lead_pawns_size[1], [6; 4]);
+    }
+
+    #[test]
+    fn material() {
+        let m = Material::new("KRPvKR").unwrap();
+        assert_eq!(m.piece_count, 5);
+        assert!(m.has_pawns && m.has_unique_pieces && !m.symmetric);
+        assert_eq!(m.pawn_count, [1, 0]);
+        let m = Material::new("KPvKPP").unwrap();
+        assert_eq!(m.pawn_count, [1, 2]);
+        assert!(Material::new("KNNvKNN").unwrap().symmetric);
+        assert!(!Material::new("KNNvK").unwrap().has_unique_pieces);
+        assert!(Material::new("KQ").is_none());
+        let (board, color) = Board::from_fen("8/8/4k3/8/2R5/8/3KP3/8 w - - 0 1").unwrap();
+        let pos = Position::new(&board, color);
+        assert_eq!(
+            (pos.material(false), pos.material(true)),
+            ("KRP".into(), "K".into())
+        );
+    }
+
+    /// The KQvK tables are solved by retrograde analysis and compressed without pairs of
+    /// symbols, which keeps them small enough to check in
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syzygy");
+
+    /// A board with the white king and queen and the black king on the squares
+    fn kqvk(wk: usize, wq: usize, bk: usize) -> Board {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            for file in 0..8 {
+                let sq = rank * 8 + file;
+                fen.push(match sq {
+                    _ if sq == wk => 'K',
+                    _ if sq == wq => 'Q',
+                    _ if sq == bk => 'k',
+                    _ => '1',
+                });
+            }
+            fen.push(if rank > 0 { '/' } else { ' ' });
+        }
+        Board::from_fen(&(fen + "w - - 0 1")).unwrap().0
+    }
+
+    fn is_mate(board: &Board, color: Color) -> bool {
+        board.is_in_check(color) && legal_moves(board, color).is_empty()
+    }
+
+    #[test]
+    fn open_and_load() {
+        let tb = Tablebase::open(FIXTURE).unwrap();
+        assert_eq!(tb.max_pieces(), 3);
+        assert!(tb.available.contains(&("KQvK".to_string(), TableKind::Wdl)));
+        assert!(tb.available.contains(&("KQvK".to_string(), TableKind::Dtz)));
+        let maps = Maps::new();
+        let path = Path::new(FIXTURE).join("KQvK.rtbw");
+        let table = Table::load(&path, TableKind::Wdl, Material::new("KQvK").unwrap(), &maps);
+        let table = table.unwrap();
+        assert_eq!((table.pairs.len(), table.pairs[0].len()), (2, 1));
+        // white always wins, so only the side of black needs a code
+        assert_ne!(table.get(0, 0).flags & FLAG_SINGLE_VALUE, 0);
+        assert_eq!(table.get(1, 0).flags & FLAG_SINGLE_VALUE, 0);
+        // the magic number tells the tables apart, the flags tell the materials apart
+        assert!(
+            Table::load(&path, TableKind::Dtz, Material::new("KQvK").unwrap(), &maps).is_none()
+        );
+        assert!(Table::load(
+            &path,
+            TableKind::Wdl,
+            Material::new("KQvKQ").unwrap(),
+            &maps
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn probe_kqvk() {
+        let tb = Tablebase::open(FIXTURE).unwrap();
+        for &wk in &[0, 18, 28] {
+            for wq in (0..64).filter(|&q| q != wk) {
+                for bk in (0..64).filter(|&k| k != wk && k != wq && !adjacent(k, wk)) {
+                    let board = kqvk(wk, wq, bk);
+                    if !board.is_in_check(Color::Black) {
+                        assert_eq!(tb.probe_wdl(&board, Color::White), Some(Wdl::Win));
+                    }
+                    // black only escapes by taking the queen or by being stalemated
+                    let moves = legal_moves(&board, Color::Black);
+                    let expected = if moves.iter().any(Move::is_capture)
+                        || (moves.is_empty() && !board.is_in_check(Color::Black))
+                    {
+                        Wdl::Draw
+                    } else {
+                        Wdl::Loss
+                    };
+                    let wdl = tb.probe_wdl(&board, Color::Black);
+                    assert_eq!(wdl, Some(expected), "{}", board.to_fen(Color::Black));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn best_moves() {
+        let tb = Tablebase::open(FIXTURE).unwrap();
+        // the queen mates on b8
+        let (board, color) = Board::from_fen("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1").unwrap();
+        assert_eq!(tb.probe_dtz(&board, color), Some(1));
+        let (mv, wdl) = tb.best_move(&board, color).unwrap();
+        assert_eq!((mv.to_string(), wdl), ("b1b8".to_string(), Wdl::Win));
+        assert!(is_mate(&after_move(&board, mv, color), !color));
+        // the only move of black runs into the mate
+        assert_eq!(tb.probe_dtz(&board, !color), Some(-2));
+        assert_eq!(tb.probe_wdl(&board, !color), Some(Wdl::Loss));
+        // black takes the queen that is left alone
+        let (board, color) = Board::from_fen("8/8/8/8/8/8/1Qk5/7K b - - 0 1").unwrap();
+        let (mv, wdl) = tb.best_move(&board, color).unwrap();
+        assert_eq!((mv.to_string(), wdl), ("c2b2".to_string(), Wdl::Draw));
+        // the stalemated king has no move at all
+        let (board, color) = Board::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(tb.probe_wdl(&board, color), Some(Wdl::Draw));
+        assert!(tb.best_move(&board, color).is_none());
+    }
+
+    #[test]
+    fn missing_tables() {
+        let dir = std::env::temp_dir().join(format!("scha256-syzygy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(matches!(
+            Tablebase::open(&dir),
+            Err(TablebaseError::NoTables(_))
+        ));
+        // the tables are only read on the first probe, a file that is gone by then counts as
+        // an unknown position
+        std::fs::copy(Path::new(FIXTURE).join("KQvK.rtbw"), dir.join("KQvK.rtbw")).unwrap();
+        std::fs::write(dir.join("KRvK.rtbw"), b"").unwrap();
+        let tb = Tablebase::open(&dir).unwrap();
+        let (board, color) = Board::from_fen("8/8/4k3/8/8/8/8/KQ6 w - - 0 1").unwrap();
+        assert_eq!(tb.probe_wdl(&board, color), Some(Wdl::Win));
+        // without the DTZ table there is no fastest win
+        assert!(tb.best_move(&board, color).is_none());
+        let (board, color) = Board::from_fen("8/8/4k3/8/8/8/8/KR6 w - - 0 1").unwrap();
+        assert_eq!(tb.probe_wdl(&board, color), None);
+        let (board, color) = Board::from_fen("8/8/4k3/8/8/8/8/KN6 w - - 0 1").unwrap();
+        assert_eq!(tb.probe_wdl(&board, color), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wdl() {
+        assert_eq!(-Wdl::Win, Wdl::Loss);
+        assert_eq!(-Wdl::CursedWin, Wdl::BlessedLoss);
+        assert_eq!(-Wdl::Draw, Wdl::Draw);
+        assert!(Wdl::Loss < Wdl::BlessedLoss && Wdl::CursedWin < Wdl::Win);
+        assert_eq!(Wdl::CursedWin.signum(), 1);
+        assert_eq!(Wdl::from_value(-5), Wdl::Loss);
+        assert_eq!(Wdl::BlessedLoss.dtz_before_zeroing(), -101);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::board::{Board, Color, CommonCoord, Coord, Field, Piece, UnsafeCoord};
 use crate::list::{array_from_fn, List};
 use crate::moves::{Move, MoveType, PromotionType};
+use crate::variant::Variant;
 
 pub type ThreatList = List<Coord, 16>;
 
@@ -8,9 +9,12 @@ pub type ThreatList = List<Coord, 16>;
 pub struct King {
     pub coord: Coord,
     pub potential_check_map: [Option<(Coord, Direction)>; 10 * 12],
-    pub castling_to_left: bool,
-    pub castling_to_right: bool,
+    /// The file of the rook the king may still castle with on its left and right side
+    pub castling_to_left: Option<i8>,
+    pub castling_to_right: Option<i8>,
     pub aggressors: ThreatList,
+    /// How often the king was checked, which only Three-check counts
+    pub checks: u8,
 }
 
 impl King {
@@ -22,9 +26,10 @@ impl King {
                 Coord::from_xy(4, 0)
             },
             potential_check_map: [None; 10 * 12],
-            castling_to_left: true,
-            castling_to_right: true,
+            castling_to_left: Some(0),
+            castling_to_right: Some(7),
             aggressors: ThreatList::new(),
+            checks: 0,
         }
     }
 
@@ -254,11 +259,15 @@ impl Board {
                 self.update_threat_mask_add_piece(mv.end);
                 self.update_threat_mask_add_piece(castle.rook_target);
             }
+            MoveType::Drop(_) => {
+                self.update_threat_mask_add_barrier(mv.end);
+                self.update_threat_mask_add_piece(mv.end);
+            }
         }
     }
 
     pub(crate) fn init_threat_mask(&mut self) {
-        for &y in &[0, 1, 6, 7] {
+        for y in 0..8 {
             for x in 0..8 {
                 let threat = Coord::from_xy(x, y);
                 for &target in self.get_causing_threats(threat).slice() {
@@ -429,6 +438,7 @@ impl Board {
             MoveType::Castle(castle) => {
                 self.remove_threat_mask_piece_at(castle.rook_pos);
             }
+            MoveType::Drop(_) => (),
         }
     }
 
@@ -487,6 +497,13 @@ impl Board {
     pub fn update_aggressors(&mut self, color: Color) {
         let mut lst = self.threat_mask.get(self.get_king(color).coord).clone();
         lst.filter(0, |&coord| self.get(coord).is_color_piece(color));
+        // the king is an ordinary piece in Antichess and untouchable next to the other one
+        // in Atomic
+        if self.variant == Variant::Antichess
+            || (self.variant == Variant::Atomic && self.are_kings_touching())
+        {
+            lst.clear();
+        }
         self.get_king_mut(color).aggressors = lst;
     }
 
@@ -0,0 +1,273 @@
+use crate::board::{Board, Color, Coord, Field, Piece};
+use crate::endgame::center_distance;
+use crate::moves::{LongMoveList, Move, MoveType};
+use crate::score::EvalParams;
+use serde::{Deserialize, Serialize};
+
+/// Rules that change how a game is won and how positions are rated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Variant {
+    Standard,
+    /// A king that reaches one of the four central squares wins
+    KingOfTheHill,
+    /// Checking the enemy king for the third time wins
+    ThreeCheck,
+    /// A capture explodes the capturing piece and all pieces but pawns next to the target,
+    /// the king cannot capture and exploding the enemy king wins
+    Atomic,
+    /// Capturing is compulsory, the king is an ordinary piece and the player who cannot
+    /// move, usually for having lost all pieces, wins
+    Antichess,
+    /// Captured pieces go to the pocket of the capturing player, who may drop them on an
+    /// empty square instead of moving
+    Crazyhouse,
+}
+
+pub const VARIANTS: [Variant; 6] = [
+    Variant::Standard,
+    Variant::KingOfTheHill,
+    Variant::ThreeCheck,
+    Variant::Atomic,
+    Variant::Antichess,
+    Variant::Crazyhouse,
+];
+
+const KING_STEPS: [(i8, i8); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+/// The squares of the hill in King of the Hill
+const HILL: [(i8, i8); 4] = [(3, 3), (4, 3), (3, 4), (4, 4)];
+
+/// The checks that win Three-check
+pub const CHECKS_TO_WIN: u8 = 3;
+
+/// The bonus for every step the king is closer to the hill
+const HILL_APPROACH_BONUS: i32 = 400;
+
+/// The bonus for the checks given so far, before the winning one
+const CHECK_BONUS: [i32; CHECKS_TO_WIN as usize] = [0, 1500, 4000];
+
+#[derive(Debug, Clone)]
+pub struct VariantFromStrError(String);
+
+impl std::fmt::Display for VariantFromStrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown variant '{}'", self.0)
+    }
+}
+
+impl std::error::Error for VariantFromStrError {}
+
+impl Variant {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::KingOfTheHill => "king of the hill",
+            Self::ThreeCheck => "three-check",
+            Self::Atomic => "atomic",
+            Self::Antichess => "antichess",
+            Self::Crazyhouse => "crazyhouse",
+        }
+    }
+
+    /// Whether the legal moves follow from checks and pins like in standard chess, the
+    /// other variants find them in [`Board::enumerate_variant_moves`]
+    pub(crate) const fn has_standard_moves(&self) -> bool {
+        matches!(
+            self,
+            Self::Standard | Self::KingOfTheHill | Self::ThreeCheck | Self::Crazyhouse
+        )
+    }
+
+    /// The score of the rules of the variant for `color`, which adds to the evaluation
+    pub(crate) fn get_score(&self, board: &Board, color: Color, params: &EvalParams) -> i32 {
+        match self {
+            Self::Standard | Self::Atomic => 0,
+            Self::KingOfTheHill => {
+                HILL_APPROACH_BONUS * (6 - center_distance(board.get_king(color).coord))
+            }
+            Self::ThreeCheck => {
+                let given = board.get_king(!color).checks.min(CHECKS_TO_WIN - 1);
+                CHECK_BONUS[given as usize]
+            }
+            // subtracting the material twice turns its rating around
+            Self::Antichess => -2 * board.get_material(color, params),
+            Self::Crazyhouse => board.get_pocket_material(color, params),
+        }
+    }
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Self::Standard
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl core::str::FromStr for Variant {
+    type Err = VariantFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VARIANTS
+            .iter()
+            .find(|variant| variant.name().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| VariantFromStrError(s.to_string()))
+    }
+}
+
+impl Board {
+    /// The color that has won by the rules of the variant, checkmate is not one of them
+    pub fn variant_winner(&self) -> Option<Color> {
+        let colors = [Color::White, Color::Black];
+        match self.variant {
+            Variant::Standard => None,
+            Variant::KingOfTheHill => colors
+                .iter()
+                .copied()
+                .find(|&c| HILL.contains(&self.get_king(c).coord.as_xy())),
+            Variant::ThreeCheck => colors
+                .iter()
+                .copied()
+                .find(|&c| self.get_king(!c).checks >= CHECKS_TO_WIN),
+            Variant::Atomic => colors.iter().copied().find(|&c| !self.has_king(!c)),
+            Variant::Antichess | Variant::Crazyhouse => None,
+        }
+    }
+
+    /// Whether the king of `color` is still on the board, it is not after an explosion
+    fn has_king(&self, color: Color) -> bool {
+        let king = match color {
+            Color::White => Field::WhiteKing,
+            Color::Black => Field::BlackKing,
+        };
+        *self.get(self.get_king(color).coord) == king
+    }
+
+    /// Whether the kings stand next to each other, which in Atomic protects both of them
+    /// as capturing one would explode the other
+    pub(crate) fn are_kings_touching(&self) -> bool {
+        let (x1, y1) = self.white_king.coord.as_xy();
+        let (x2, y2) = self.black_king.coord.as_xy();
+        (x1 - x2).abs() <= 1 && (y1 - y2).abs() <= 1
+    }
+
+    fn get_material(&self, color: Color, params: &EvalParams) -> i32 {
+        let mut material = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                match *self.get(Coord::from_xy(x, y)) {
+                    Field::WhitePiece(p) if color == Color::White => {
+                        material += params.value_from_piece(p)
+                    }
+                    Field::BlackPiece(p) if color == Color::Black => {
+                        material += params.value_from_piece(p)
+                    }
+                    _ => (),
+                }
+            }
+        }
+        material
+    }
+
+    /// Remove the piece that captured on `center` and all pieces but pawns next to it
+    pub(crate) fn explode(&mut self, center: Coord) {
+        self.pop_field(center, Field::Empty);
+        for &(dx, dy) in &KING_STEPS {
+            if let Some((coord, field)) = self.get_if_safe(center.rel(dx, dy)) {
+                match field {
+                    Field::Empty
+                    | Field::WhitePiece(Piece::Pawn)
+                    | Field::BlackPiece(Piece::Pawn) => (),
+                    _ => {
+                        self.pop_field(coord, Field::Empty);
+                        self.revoke_castling_with(coord);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the king of `color` survived and cannot be captured by the rules of Atomic
+    fn is_atomic_king_safe(&self, color: Color) -> bool {
+        self.has_king(color)
+            && (!self.has_king(!color) || self.are_kings_touching() || !self.is_in_check(color))
+    }
+
+    /// Step with the king like with any other piece, in Atomic it must not capture
+    fn list_king_steps(&self, coord: Coord, color: Color, list: &mut LongMoveList) {
+        for &(dx, dy) in &KING_STEPS {
+            if let Some((end, field)) = self.get_if_safe(coord.rel(dx, dy)) {
+                let move_type = if field == Field::Empty {
+                    MoveType::Regular
+                } else if self.variant != Variant::Atomic && self.is_capturable(field, color) {
+                    MoveType::Capture
+                } else {
+                    continue;
+                };
+                list.append(Move {
+                    start: coord,
+                    end,
+                    move_type,
+                })
+            }
+        }
+    }
+
+    /// The legal moves of `color` in the variants without standard moves: Atomic plays
+    /// every move on a copy of the board to see whether the own king survives, Antichess
+    /// keeps only the captures if there are any
+    pub(crate) fn enumerate_variant_moves(&self, color: Color, list: &mut LongMoveList) {
+        let start = list.slice().len();
+        for y in 0..8 {
+            for x in 0..8 {
+                let coord = Coord::from_xy(x, y);
+                match *self.get(coord) {
+                    Field::WhitePiece(p) if color == Color::White => {
+                        self.list_piece_moves(coord, p, color, list)
+                    }
+                    Field::BlackPiece(p) if color == Color::Black => {
+                        self.list_piece_moves(coord, p, color, list)
+                    }
+                    Field::WhiteKing if color == Color::White => {
+                        self.list_king_steps(coord, color, list)
+                    }
+                    Field::BlackKing if color == Color::Black => {
+                        self.list_king_steps(coord, color, list)
+                    }
+                    _ => (),
+                }
+            }
+        }
+        if self.variant == Variant::Atomic {
+            if self.is_atomic_king_safe(color) {
+                let king = self.get_king(color);
+                if let Some(rook_x) = king.castling_to_right {
+                    self.list_castling_move(king.coord, color, rook_x, true, list);
+                }
+                if let Some(rook_x) = king.castling_to_left {
+                    self.list_castling_move(king.coord, color, rook_x, false, list);
+                }
+            }
+            list.filter(start, |&mv| {
+                let mut board = self.clone();
+                board.do_move(mv);
+                board.is_atomic_king_safe(color)
+            });
+        } else if list.slice()[start..].iter().any(Move::is_capture) {
+            list.filter(start, Move::is_capture);
+        }
+    }
+}
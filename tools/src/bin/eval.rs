@@ -0,0 +1,103 @@
+extern crate scha256_engine as engine;
+
+use clap::Arg;
+use engine::board::{Board, Coord};
+use engine::decision::evaluate_explained;
+use engine::fen::START_FEN;
+use engine::score::{EvalParams, EvalTerms};
+use engine::variant::Variant;
+
+#[derive(Debug)]
+enum Error {
+    InvalidParams(engine::score::EvalParamsError),
+    InvalidFen(engine::fen::FenError),
+    UnknownVariant(engine::variant::VariantFromStrError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidParams(e) => write!(f, "evaluation parameter error: {}", e),
+            Self::InvalidFen(e) => write!(f, "invalid fen: {}", e),
+            Self::UnknownVariant(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn print_terms(name: &str, white: &EvalTerms, black: &EvalTerms) {
+    println!("{:<16}{:>10}{:>10}{:>10}", name, "white", "black", "total");
+    for &(term, w, b) in &[
+        ("material", white.material, black.material),
+        ("threat bounty", white.threat_bounty, black.threat_bounty),
+        ("centralization", white.centralization, black.centralization),
+        ("border penalty", white.border_penalty, black.border_penalty),
+        ("variant", white.variant, black.variant),
+        ("total", white.total(), black.total()),
+    ] {
+        println!("{:<16}{:>10}{:>10}{:>10}", term, w, b, w - b);
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let matches = clap::app_from_crate!()
+        .arg(Arg::new("fen").value_name("FEN").default_value(START_FEN))
+        .arg(
+            Arg::new("params")
+                .long("params")
+                .short('p')
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::new("variant")
+                .long("variant")
+                .short('v')
+                .value_name("NAME")
+                .default_value("standard"),
+        )
+        .arg(Arg::new("json").long("json").short('j'))
+        .arg(Arg::new("squares").long("squares").short('s'))
+        .get_matches();
+    let params = match matches.value_of("params") {
+        Some(path) => EvalParams::load(path).map_err(Error::InvalidParams)?,
+        None => EvalParams::default(),
+    };
+    let (mut board, _) =
+        Board::from_fen(matches.value_of("fen").unwrap()).map_err(Error::InvalidFen)?;
+    let variant: Variant = matches
+        .value_of("variant")
+        .unwrap()
+        .parse()
+        .map_err(Error::UnknownVariant)?;
+    board.set_variant(variant);
+    let trace = evaluate_explained(&board, &params);
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&trace).unwrap());
+        return Ok(());
+    }
+    board.output_terminal(true, &[]);
+    println!();
+    print_terms("", &trace.white, &trace.black);
+    if let Some(score) = trace.endgame {
+        println!("known endgame, which scores {} instead", score);
+    }
+    if matches.is_present("squares") {
+        for square in &trace.squares {
+            if square.white == EvalTerms::default() && square.black == EvalTerms::default() {
+                continue;
+            }
+            println!();
+            let coord = Coord::from_xy(square.x, square.y);
+            let name = format!("{} {}", coord, board.get(coord).repr().1);
+            print_terms(&name, &square.white, &square.black);
+        }
+    }
+    println!("\nscore (white's perspective): {}", trace.score());
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
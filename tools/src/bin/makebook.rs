@@ -0,0 +1,293 @@
+extern crate scha256_engine as engine;
+
+use clap::Arg;
+use engine::board::{Board, Color};
+use engine::book::{encode_move, Book, BookEntry};
+use engine::fen::START_FEN;
+use std::collections::HashMap;
+
+#[derive(Debug)]
+enum Error {
+    ArgumentParsingError(Box<dyn std::error::Error>),
+    Io(std::io::Error),
+    NoEntries,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ArgumentParsingError(e) => write!(f, "argument parsing error: {}", e),
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::NoEntries => write!(f, "no move passed the filters"),
+        }
+    }
+}
+
+struct BookConfig {
+    games: Vec<String>,
+    output: String,
+    max_ply: u32,
+    min_games: u32,
+    min_win_rate: f64,
+}
+
+fn args_as_config() -> Result<BookConfig, Error> {
+    let matches = clap::app_from_crate!()
+        .arg(
+            Arg::new("games")
+                .value_name("PGN")
+                .required(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .default_value("book.bin"),
+        )
+        .arg(
+            Arg::new("max-ply")
+                .long("max-ply")
+                .short('d')
+                .value_name("PLIES")
+                .default_value("16"),
+        )
+        .arg(
+            Arg::new("min-games")
+                .long("min-games")
+                .short('g')
+                .value_name("COUNT")
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("min-win-rate")
+                .long("min-win-rate")
+                .short('w')
+                .value_name("RATE")
+                .default_value("0"),
+        )
+        .get_matches();
+    let arg_err = |e| Error::ArgumentParsingError(Box::new(e));
+    Ok(BookConfig {
+        games: matches
+            .values_of("games")
+            .unwrap()
+            .map(ToString::to_string)
+            .collect(),
+        output: matches.value_of("output").unwrap().to_string(),
+        max_ply: matches.value_of_t("max-ply").map_err(arg_err)?,
+        min_games: matches.value_of_t("min-games").map_err(arg_err)?,
+        min_win_rate: matches.value_of_t("min-win-rate").map_err(arg_err)?,
+    })
+}
+
+/// The tags and the moves of a single game without comments, variations and move numbers
+#[derive(Default)]
+struct Game {
+    fen: Option<String>,
+    result: Option<String>,
+    moves: Vec<String>,
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+fn parse_tag(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = line.split_at(line.find(char::is_whitespace)?);
+    Some((name, value.trim().trim_matches('"')))
+}
+
+fn push_token(token: &mut String, game: &mut Game) {
+    // `12.e4` and `12...e5` carry the move number in front of the move, but `0-0` castles
+    let mv = match token
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .strip_prefix('.')
+    {
+        Some(mv) => mv.trim_start_matches('.'),
+        None => token.as_str(),
+    };
+    if is_result(mv) {
+        game.result.get_or_insert_with(|| mv.to_string());
+    } else if !mv.is_empty() && !mv.starts_with('$') && !mv.chars().all(|c| c.is_ascii_digit()) {
+        game.moves.push(mv.to_string());
+    }
+    token.clear();
+}
+
+/// Add the move text to `game`, `depth` tracks the nesting of variations across lines
+fn parse_movetext(line: &str, depth: &mut u32, in_comment: &mut bool, game: &mut Game) {
+    let mut token = String::new();
+    for c in line.chars() {
+        match c {
+            _ if *in_comment => *in_comment = c != '}',
+            '{' | ';' | '(' => {
+                push_token(&mut token, game);
+                match c {
+                    '{' => *in_comment = true,
+                    ';' => break,
+                    _ => *depth += 1,
+                }
+            }
+            ')' => *depth = depth.saturating_sub(1),
+            _ if *depth > 0 => (),
+            _ if c.is_whitespace() => push_token(&mut token, game),
+            _ => token.push(c),
+        }
+    }
+    push_token(&mut token, game);
+}
+
+/// Split a PGN database into games
+fn parse_pgn(content: &str) -> Vec<Game> {
+    let mut games = vec![];
+    let mut game = Game::default();
+    let (mut depth, mut in_comment, mut in_tags) = (0, false, false);
+    for line in content.lines() {
+        if !in_comment && line.starts_with('[') {
+            // the first tag starts a new game, even if the last one had no moves
+            if !in_tags {
+                let finished = std::mem::take(&mut game);
+                if !finished.moves.is_empty() {
+                    games.push(finished);
+                }
+                depth = 0;
+                in_tags = true;
+            }
+            match parse_tag(line) {
+                Some(("FEN", fen)) => game.fen = Some(fen.to_string()),
+                Some(("Result", result)) => game.result = Some(result.to_string()),
+                _ => (),
+            }
+        } else if !line.starts_with('%') {
+            in_tags &= line.trim().is_empty();
+            parse_movetext(line, &mut depth, &mut in_comment, &mut game);
+        }
+    }
+    if !game.moves.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+/// The results of a move from the perspective of the moving side
+#[derive(Default, Clone, Copy)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+impl MoveStats {
+    fn win_rate(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games as f64
+    }
+
+    /// Polyglot weighs a move with two points per win and one per draw
+    fn points(&self) -> u64 {
+        2 * self.wins as u64 + self.draws as u64
+    }
+}
+
+/// Count the moves of a game in `stats` up to `max_ply`, nothing is counted if a move is illegal
+fn add_game(
+    game: &Game,
+    max_ply: u32,
+    stats: &mut HashMap<(u64, u16), MoveStats>,
+) -> Result<(), String> {
+    let winner = match game.result.as_deref() {
+        Some("1-0") => Some(Color::White),
+        Some("0-1") => Some(Color::Black),
+        Some("1/2-1/2") => None,
+        _ => return Err("unfinished game".to_string()),
+    };
+    let (mut board, mut color) = Board::from_fen(game.fen.as_deref().unwrap_or(START_FEN))
+        .map_err(|e| format!("invalid fen: {}", e))?;
+    let mut played = vec![];
+    for san in &game.moves {
+        if board.ply() >= max_ply {
+            break;
+        }
+        let mv = board
+            .parse_san(color, san)
+            .map_err(|e| format!("move '{}': {}", san, e))?;
+        played.push((board.polyglot_key(color), encode_move(&mv), color));
+        board.do_move(mv);
+        color = !color;
+        board.update_aggressors(color);
+    }
+    for (key, mv, color) in played {
+        let entry = stats.entry((key, mv)).or_default();
+        entry.games += 1;
+        match winner {
+            Some(c) if c == color => entry.wins += 1,
+            None => entry.draws += 1,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn build_book(config: &BookConfig, stats: &HashMap<(u64, u16), MoveStats>) -> Book {
+    let kept: Vec<_> = stats
+        .iter()
+        .filter(|(_, s)| s.games >= config.min_games && s.win_rate() >= config.min_win_rate)
+        .collect();
+    // scale the weights down if the points do not fit into 16 bits
+    let max = kept.iter().map(|(_, s)| s.points()).max().unwrap_or(0);
+    let scale = |points: u64| match max {
+        0..=0xffff => points,
+        _ => points * 0xffff / max,
+    };
+    Book::from_entries(
+        kept.into_iter()
+            .map(|(&(key, mv), s)| BookEntry {
+                key,
+                mv,
+                weight: scale(s.points()) as u16,
+                learn: 0,
+            })
+            .filter(|e| e.weight > 0)
+            .collect(),
+    )
+}
+
+fn run() -> Result<(), Error> {
+    let config = args_as_config()?;
+    let mut stats = HashMap::new();
+    let (mut count, mut skipped) = (0, 0);
+    for path in &config.games {
+        let content = std::fs::read_to_string(path).map_err(Error::Io)?;
+        for (n, game) in parse_pgn(&content).iter().enumerate() {
+            match add_game(game, config.max_ply, &mut stats) {
+                Ok(()) => count += 1,
+                Err(e) => {
+                    eprintln!("skipping game {} of '{}': {}", n + 1, path, e);
+                    skipped += 1
+                }
+            }
+        }
+    }
+    println!("read {} games, skipped {}", count, skipped);
+    let book = build_book(&config, &stats);
+    if book.entries().is_empty() {
+        return Err(Error::NoEntries);
+    }
+    std::fs::write(&config.output, book.to_bytes()).map_err(Error::Io)?;
+    println!(
+        "wrote {} of {} moves to '{}'",
+        book.entries().len(),
+        stats.len(),
+        config.output
+    );
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,274 @@
+extern crate scha256_engine as engine;
+
+use clap::Arg;
+use engine::board::Board;
+use engine::decision::get_white_board_score;
+use engine::score::EvalParams;
+use serde_json::Value;
+
+/// Parameters that only influence the move ordering and not the evaluation
+const ORDERING_PARAMS: &[&str] = &["castling_move_score", "capture_attacker_shift"];
+
+#[derive(Debug)]
+enum Error {
+    ArgumentParsingError(Box<dyn std::error::Error>),
+    Io(std::io::Error),
+    InvalidParams(engine::score::EvalParamsError),
+    InvalidPosition(usize, String),
+    NoPositions,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ArgumentParsingError(e) => write!(f, "argument parsing error: {}", e),
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::InvalidParams(e) => write!(f, "evaluation parameter error: {}", e),
+            Self::InvalidPosition(line, e) => write!(f, "invalid position in line {}: {}", line, e),
+            Self::NoPositions => write!(f, "the position file does not contain any positions"),
+        }
+    }
+}
+
+struct TuneConfig {
+    positions: String,
+    params: EvalParams,
+    output: String,
+    iterations: u32,
+    step: i64,
+    k: Option<f64>,
+}
+
+fn args_as_config() -> Result<TuneConfig, Error> {
+    let matches = clap::app_from_crate!()
+        .arg(Arg::new("positions").value_name("POSITIONS").required(true))
+        .arg(
+            Arg::new("params")
+                .long("params")
+                .short('p')
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .default_value("tuned.toml"),
+        )
+        .arg(
+            Arg::new("iterations")
+                .long("iterations")
+                .short('i')
+                .value_name("COUNT")
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("step")
+                .long("step")
+                .short('s')
+                .value_name("STEP")
+                .default_value("16"),
+        )
+        .arg(Arg::new("k").long("scaling").short('k').value_name("K"))
+        .get_matches();
+    let arg_err = |e| Error::ArgumentParsingError(Box::new(e));
+    Ok(TuneConfig {
+        positions: matches.value_of("positions").unwrap().to_string(),
+        params: match matches.value_of("params") {
+            Some(path) => EvalParams::load(path).map_err(Error::InvalidParams)?,
+            None => EvalParams::default(),
+        },
+        output: matches.value_of("output").unwrap().to_string(),
+        iterations: matches.value_of_t("iterations").map_err(arg_err)?,
+        step: matches.value_of_t("step").map_err(arg_err)?,
+        k: match matches.value_of("k") {
+            Some(_) => Some(matches.value_of_t("k").map_err(arg_err)?),
+            None => None,
+        },
+    })
+}
+
+/// Parse a game result from white's perspective
+fn parse_result(s: &str) -> Option<f64> {
+    match s.trim_matches(|c| c == '[' || c == ']' || c == '"' || c == ';') {
+        "1-0" | "1.0" | "1" => Some(1.0),
+        "0-1" | "0.0" | "0" => Some(0.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        _ => None,
+    }
+}
+
+/// Read a file where every line holds a FEN followed by the game result
+/// e.g. `rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1 [1/2-1/2]`
+fn load_positions(path: &str) -> Result<Vec<(Board, f64)>, Error> {
+    let content = std::fs::read_to_string(path).map_err(Error::Io)?;
+    let mut positions = vec![];
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |e: &str| Error::InvalidPosition(n + 1, e.to_string());
+        let split = line
+            .rfind(char::is_whitespace)
+            .ok_or_else(|| err("missing result"))?;
+        let result = parse_result(line[split..].trim()).ok_or_else(|| err("invalid result"))?;
+        let (board, _) = Board::from_fen(&line[..split]).map_err(|e| err(&e.to_string()))?;
+        positions.push((board, result));
+    }
+    if positions.is_empty() {
+        Err(Error::NoPositions)
+    } else {
+        Ok(positions)
+    }
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + (-k * score).exp())
+}
+
+fn mean_squared_error(positions: &[(Board, f64)], params: &EvalParams, k: f64) -> f64 {
+    let sum: f64 = positions
+        .iter()
+        .map(|(board, result)| {
+            let e = result - sigmoid(get_white_board_score(board, params) as f64, k);
+            e * e
+        })
+        .sum();
+    sum / positions.len() as f64
+}
+
+/// Find the sigmoid scaling that fits the untuned evaluation best
+fn find_scaling(positions: &[(Board, f64)], params: &EvalParams) -> f64 {
+    let scores: Vec<(f64, f64)> = positions
+        .iter()
+        .map(|(board, result)| (get_white_board_score(board, params) as f64, *result))
+        .collect();
+    let error = |k| {
+        scores
+            .iter()
+            .map(|&(s, r)| (r - sigmoid(s, k)).powi(2))
+            .sum::<f64>()
+    };
+    let (mut lo, mut hi) = (0.0, 0.02);
+    for _ in 0..100 {
+        let (m1, m2) = (lo + (hi - lo) / 3.0, hi - (hi - lo) / 3.0);
+        if error(m1) < error(m2) {
+            hi = m2
+        } else {
+            lo = m1
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// The location of a single tunable integer inside the serialized parameters
+#[derive(Debug, Clone)]
+struct ParamIndex {
+    name: String,
+    index: Option<usize>,
+}
+
+impl ParamIndex {
+    fn get_mut<'a>(&self, value: &'a mut Value) -> &'a mut Value {
+        let v = &mut value[self.name.as_str()];
+        match self.index {
+            Some(i) => &mut v[i],
+            None => v,
+        }
+    }
+}
+
+/// The integer parameters, other numbers and values are left as they are
+fn tunable_params(value: &Value) -> Vec<ParamIndex> {
+    let is_integer = |v: &Value| matches!(v, Value::Number(n) if n.is_i64());
+    let mut indices = vec![];
+    if let Value::Object(map) = value {
+        for (name, v) in map {
+            if ORDERING_PARAMS.contains(&name.as_str()) {
+                continue;
+            }
+            match v {
+                v if is_integer(v) => indices.push(ParamIndex {
+                    name: name.clone(),
+                    index: None,
+                }),
+                Value::Array(arr) => {
+                    indices.extend((0..arr.len()).filter(|&i| is_integer(&arr[i])).map(|i| {
+                        ParamIndex {
+                            name: name.clone(),
+                            index: Some(i),
+                        }
+                    }))
+                }
+                _ => (),
+            }
+        }
+    }
+    indices
+}
+
+fn tune(config: &TuneConfig, positions: &[(Board, f64)], k: f64) -> EvalParams {
+    let mut value = serde_json::to_value(&config.params).unwrap();
+    let indices = tunable_params(&value);
+    let mut best = mean_squared_error(positions, &config.params, k);
+    println!("initial error: {:.8}", best);
+    let mut step = config.step;
+    for iteration in 0..config.iterations {
+        let mut improved = false;
+        for index in &indices {
+            let old = index.get_mut(&mut value).as_i64().unwrap();
+            for &candidate in &[old + step, old - step] {
+                *index.get_mut(&mut value) = candidate.into();
+                let params: EvalParams = serde_json::from_value(value.clone()).unwrap();
+                let error = mean_squared_error(positions, &params, k);
+                if error < best {
+                    best = error;
+                    improved = true;
+                    break;
+                }
+                *index.get_mut(&mut value) = old.into();
+            }
+        }
+        println!(
+            "iteration {}: error {:.8}, step {}",
+            iteration + 1,
+            best,
+            step
+        );
+        if !improved {
+            if step == 1 {
+                break;
+            }
+            step = (step / 2).max(1);
+        }
+    }
+    serde_json::from_value(value).unwrap()
+}
+
+fn run() -> Result<(), Error> {
+    let config = args_as_config()?;
+    let positions = load_positions(&config.positions)?;
+    println!("loaded {} positions", positions.len());
+    let k = match config.k {
+        Some(k) => k,
+        None => find_scaling(&positions, &config.params),
+    };
+    println!("sigmoid scaling: {}", k);
+    let params = tune(&config, &positions, k);
+    let content = if config.output.ends_with(".json") {
+        params.to_json_string()
+    } else {
+        params.to_toml_string()
+    };
+    std::fs::write(&config.output, content).map_err(Error::Io)?;
+    println!("wrote tuned parameters to '{}'", config.output);
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,326 @@
+extern crate scha256_engine as engine;
+
+use clap::Arg;
+use engine::board::{Board, Color};
+use engine::book::Book;
+use engine::clock::{Bonus, Clock, TimeControl};
+use engine::decision::{
+    analyze_until, decide_until, Config, Observer, SearchProgress, DEFAULT_CONFIG,
+};
+use engine::fen::START_FEN;
+use engine::moves::{LongMoveList, Move, MoveType};
+use engine::score::{EvalParams, Score};
+use engine::syzygy::Tablebase;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const MAX_MULTI_PV: usize = 256;
+const MAX_THREADS: usize = 256;
+/// The depth of a search that only ends with `stop` or when its time is up
+const UNLIMITED_DEPTH: u32 = 64;
+
+#[derive(Debug)]
+enum Error {
+    InvalidParams(engine::score::EvalParamsError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidParams(e) => write!(f, "evaluation parameter error: {}", e),
+        }
+    }
+}
+
+/// A search running in the background, which prints its best move when it is done
+struct Search {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+struct Engine {
+    config: Config,
+    board: Board,
+    color: Color,
+    /// Castling is written as the king capturing its own rook, like the GUIs do in Chess960
+    chess960: bool,
+    search: Option<Search>,
+}
+
+impl Engine {
+    fn new(config: Config, chess960: bool) -> Self {
+        let (board, color) = Board::from_fen(START_FEN).unwrap();
+        Self {
+            config,
+            board,
+            color,
+            chess960,
+            search: None,
+        }
+    }
+
+    /// End the running search and wait until it printed its best move
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            search.handle.join().unwrap();
+        }
+    }
+
+    fn uci(&self) {
+        println!("id name {} {}", clap::crate_name!(), clap::crate_version!());
+        println!("id author {}", clap::crate_authors!());
+        println!(
+            "option name MultiPV type spin default {} min 1 max {}",
+            DEFAULT_CONFIG.multi_pv, MAX_MULTI_PV
+        );
+        println!(
+            "option name Threads type spin default {} min 1 max {}",
+            DEFAULT_CONFIG.threads, MAX_THREADS
+        );
+        println!("option name BookFile type string default <empty>");
+        println!("option name SyzygyPath type string default <empty>");
+        println!("option name Seed type string default <empty>");
+        println!("option name UCI_Chess960 type check default false");
+        println!("uciok");
+    }
+
+    /// `setoption name <name> value <value>`
+    fn set_option(&mut self, args: &[&str]) {
+        let value_pos = args.iter().position(|&a| a == "value");
+        let name = args
+            .get(1..value_pos.unwrap_or(args.len()))
+            .map(|a| a.join(" "))
+            .unwrap_or_default();
+        let value = value_pos.map(|p| args[p + 1..].join(" "));
+        match (name.to_lowercase().as_str(), value) {
+            ("multipv", Some(v)) => match v.parse::<usize>() {
+                Ok(n) => self.config.multi_pv = n.clamp(1, MAX_MULTI_PV),
+                Err(_) => println!("info string invalid MultiPV value '{}'", v),
+            },
+            ("threads", Some(v)) => match v.parse::<usize>() {
+                Ok(n) => self.config.threads = n.clamp(1, MAX_THREADS),
+                Err(_) => println!("info string invalid Threads value '{}'", v),
+            },
+            ("bookfile", v) if matches!(v.as_deref(), None | Some("<empty>")) => {
+                self.config.book = None
+            }
+            ("bookfile", Some(v)) => match Book::load(&v) {
+                Ok(book) => self.config.book = Some(Arc::new(book)),
+                Err(e) => println!("info string cannot load book '{}': {}", v, e),
+            },
+            ("syzygypath", v) if matches!(v.as_deref(), None | Some("<empty>")) => {
+                self.config.tablebase = None
+            }
+            ("syzygypath", Some(v)) => match Tablebase::open(&v) {
+                Ok(tablebase) => {
+                    println!(
+                        "info string found tables with up to {} pieces",
+                        tablebase.max_pieces()
+                    );
+                    self.config.tablebase = Some(Arc::new(tablebase))
+                }
+                Err(e) => println!("info string cannot open tablebase '{}': {}", v, e),
+            },
+            ("seed", v) if matches!(v.as_deref(), None | Some("<empty>")) => {
+                self.config.seed = None
+            }
+            ("seed", Some(v)) => match v.parse::<u64>() {
+                Ok(seed) => self.config.seed = Some(seed),
+                Err(_) => println!("info string invalid Seed value '{}'", v),
+            },
+            ("uci_chess960", v) => self.chess960 = v.as_deref() == Some("true"),
+            _ => println!("info string unknown option '{}'", name),
+        }
+    }
+
+    /// `position (startpos | fen <fen>) [moves <move>...]`
+    fn position(&mut self, args: &[&str]) {
+        let moves_pos = args.iter().position(|&a| a == "moves");
+        let setup = &args[..moves_pos.unwrap_or(args.len())];
+        let fen = match setup.first() {
+            Some(&"startpos") => START_FEN.to_string(),
+            Some(&"fen") => setup[1..].join(" "),
+            _ => return println!("info string invalid position command"),
+        };
+        match Board::from_fen(&fen) {
+            Ok((board, color)) => {
+                self.board = board;
+                self.color = color;
+            }
+            Err(e) => return println!("info string invalid fen: {}", e),
+        }
+        for mv in moves_pos.map_or(&[][..], |p| &args[p + 1..]) {
+            let mut moves = LongMoveList::new();
+            self.board.enumerate_all_moves_by(self.color, &mut moves);
+            // both notations of castling are accepted regardless of the option
+            match moves
+                .slice()
+                .iter()
+                .find(|&&m| m.to_string() == *mv || format_move(m, true) == *mv)
+            {
+                Some(&m) => {
+                    self.board.do_move(m);
+                    self.color = !self.color;
+                    self.board.update_aggressors(self.color);
+                }
+                None => return println!("info string illegal move '{}'", mv),
+            }
+        }
+    }
+
+    /// The configuration of `go [depth <plies>] [movetime <ms>] [wtime <ms>] [btime <ms>]
+    /// [winc <ms>] [binc <ms>] [infinite]` and whether the search waits for `stop`
+    fn go_config(&self, args: &[&str]) -> (Config, bool) {
+        let value = |name| {
+            let p = args.iter().position(|&a| a == name)?;
+            args.get(p + 1)?.parse::<u64>().ok()
+        };
+        let mut config = self.config.clone();
+        let infinite = args.contains(&"infinite");
+        let (time, increment) = match self.color {
+            Color::White => ("wtime", "winc"),
+            Color::Black => ("btime", "binc"),
+        };
+        if let Some(ms) = value("movetime") {
+            config.move_time = Some(Duration::from_millis(ms));
+        } else if let Some(ms) = value(time) {
+            let remaining = Duration::from_millis(ms);
+            let control = TimeControl {
+                base: remaining,
+                bonus: Bonus::Increment(Duration::from_millis(value(increment).unwrap_or(0))),
+            };
+            // plan the time like in a match of the web interface
+            Clock::resume(control, [remaining; 2], None).apply(self.color, &mut config);
+        }
+        if infinite || config.move_time.is_some() {
+            config.depth = UNLIMITED_DEPTH;
+        }
+        if infinite {
+            config.move_time = None;
+        }
+        if let Some(depth) = value("depth") {
+            config.depth = (depth.clamp(1, UNLIMITED_DEPTH as u64) - 1) as u32;
+        }
+        (config, infinite)
+    }
+
+    /// Start searching in the background, `stop` ends the search early
+    fn go(&mut self, args: &[&str]) {
+        self.stop();
+        let (config, infinite) = self.go_config(args);
+        let (board, color, chess960) = (self.board.clone(), self.color, self.chess960);
+        let stop = Arc::new(AtomicBool::new(false));
+        let search_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let observer: Observer = Arc::new(move |progress| print_progress(progress, chess960));
+            // book moves are only played outside of the multi-PV analysis
+            let results = if config.multi_pv > 1 {
+                analyze_until(&board, color, config, Some(observer), search_stop.clone())
+            } else {
+                decide_until(&board, color, config, Some(observer), search_stop.clone())
+                    .into_iter()
+                    .collect()
+            };
+            // an infinite search must not report its move before it is stopped
+            while infinite && !search_stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            match results.first() {
+                Some(result) => println!("bestmove {}", format_move(result.best_move, chess960)),
+                None => println!("bestmove 0000"),
+            }
+        });
+        self.search = Some(Search { stop, handle });
+    }
+}
+
+fn format_move(mv: Move, chess960: bool) -> String {
+    match mv.move_type {
+        MoveType::Castle(castle) if chess960 => format!("{}{}", mv.start, castle.rook_pos),
+        _ => mv.to_string(),
+    }
+}
+
+fn format_score(progress: &SearchProgress) -> String {
+    let plies = progress.pv.len() as i32;
+    match progress.score {
+        Score::Value(v) => format!("cp {}", v),
+        Score::MeWins => format!("mate {}", (plies + 1) / 2),
+        Score::EnemyWins => format!("mate -{}", plies / 2),
+        Score::Stalemate | Score::EnemyStalemate => "cp 0".to_string(),
+    }
+}
+
+fn print_progress(progress: &SearchProgress, chess960: bool) {
+    let pv: Vec<String> = progress
+        .pv
+        .iter()
+        .map(|&mv| format_move(mv, chess960))
+        .collect();
+    let hashfull = progress
+        .hashfull
+        .map(|h| format!(" hashfull {}", h))
+        .unwrap_or_default();
+    println!(
+        "info multipv {} depth {} seldepth {} score {} nodes {} nps {}{} time {} pv {}",
+        progress.multipv,
+        progress.depth,
+        progress.seldepth,
+        format_score(progress),
+        progress.nodes,
+        progress.nps,
+        hashfull,
+        progress.elapsed.as_millis(),
+        pv.join(" ")
+    );
+}
+
+fn run() -> Result<(), Error> {
+    let matches = clap::app_from_crate!()
+        .arg(
+            Arg::new("params")
+                .long("params")
+                .short('p')
+                .value_name("FILE"),
+        )
+        .get_matches();
+    let mut config = DEFAULT_CONFIG;
+    if let Some(path) = matches.value_of("params") {
+        config.eval = EvalParams::load(path).map_err(Error::InvalidParams)?;
+    }
+    let mut engine = Engine::new(config, false);
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.first() {
+            Some(&"uci") => engine.uci(),
+            Some(&"isready") => println!("readyok"),
+            Some(&"ucinewgame") => {
+                engine.stop();
+                engine = Engine::new(engine.config.clone(), engine.chess960)
+            }
+            Some(&"setoption") => engine.set_option(&args[1..]),
+            Some(&"position") => engine.position(&args[1..]),
+            Some(&"go") => engine.go(&args[1..]),
+            Some(&"stop") => engine.stop(),
+            Some(&"quit") => break,
+            _ => (),
+        }
+    }
+    engine.stop();
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,8 +1,12 @@
 #[derive(Debug)]
 pub enum Error {
     ArgumentParsingError(Box<dyn std::error::Error>),
+    BookError(engine::book::BookError),
+    EvalParamsError(engine::score::EvalParamsError),
     InvalidServerConfiguration(Box<dyn std::error::Error>),
     LaunchError(rocket::error::LaunchError),
+    StorageError(engine::storage::StorageError),
+    TablebaseError(engine::syzygy::TablebaseError),
     TemplateParsingError(Box<dyn std::error::Error>),
     TemplateRenderingError(Box<dyn std::error::Error>),
 }
@@ -11,8 +15,12 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::ArgumentParsingError(e) => write!(f, "argument parsing error: {}", e),
+            Self::BookError(e) => write!(f, "opening book error: {}", e),
+            Self::EvalParamsError(e) => write!(f, "evaluation parameter error: {}", e),
             Self::InvalidServerConfiguration(e) => write!(f, "invalid server configuration: {}", e),
             Self::LaunchError(e) => write!(f, "launch error: {}", e),
+            Self::StorageError(e) => write!(f, "match storage error: {}", e),
+            Self::TablebaseError(e) => write!(f, "tablebase error: {}", e),
             Self::TemplateParsingError(e) => write!(f, "template parsing error: {}", e),
             Self::TemplateRenderingError(e) => write!(f, "template rendering error: {}", e),
         }
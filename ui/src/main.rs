@@ -48,7 +48,73 @@ fn args_as_config() -> Result<ServerConfig, Error> {
                 .value_name("LOCATION")
                 .default_value("/"),
         )
+        .arg(
+            Arg::new("eval-params")
+                .long("eval-params")
+                .short('e')
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::new("multi-pv")
+                .long("multi-pv")
+                .short('m')
+                .value_name("COUNT")
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .short('t')
+                .value_name("COUNT")
+                .default_value("1"),
+        )
+        .arg(Arg::new("ponder").long("ponder"))
+        .arg(Arg::new("book").long("book").short('b').value_name("FILE"))
+        .arg(
+            Arg::new("book-depth")
+                .long("book-depth")
+                .value_name("PLIES")
+                .default_value("16"),
+        )
+        .arg(Arg::new("syzygy").long("syzygy").value_name("DIR"))
+        .arg(Arg::new("seed").long("seed").value_name("NUMBER"))
+        .arg(
+            Arg::new("storage")
+                .long("storage")
+                .short('s')
+                .value_name("DIR"),
+        )
         .get_matches();
+    let mut engine_config = engine::decision::DEFAULT_CONFIG;
+    engine_config.multi_pv = matches
+        .value_of_t("multi-pv")
+        .map_err(|e| Error::ArgumentParsingError(Box::new(e)))?;
+    engine_config.threads = matches
+        .value_of_t("threads")
+        .map_err(|e| Error::ArgumentParsingError(Box::new(e)))?;
+    engine_config.ponder = matches.is_present("ponder");
+    if let Some(path) = matches.value_of("book") {
+        let book = engine::book::Book::load(path).map_err(Error::BookError)?;
+        engine_config.book = Some(std::sync::Arc::new(book));
+    }
+    engine_config.book_depth = matches
+        .value_of_t("book-depth")
+        .map_err(|e| Error::ArgumentParsingError(Box::new(e)))?;
+    if matches.is_present("seed") {
+        engine_config.seed = Some(
+            matches
+                .value_of_t("seed")
+                .map_err(|e| Error::ArgumentParsingError(Box::new(e)))?,
+        );
+    }
+    if let Some(dir) = matches.value_of("syzygy") {
+        let tablebase = engine::syzygy::Tablebase::open(dir).map_err(Error::TablebaseError)?;
+        engine_config.tablebase = Some(std::sync::Arc::new(tablebase));
+    }
+    if let Some(path) = matches.value_of("eval-params") {
+        engine_config.eval =
+            engine::score::EvalParams::load(path).map_err(Error::EvalParamsError)?;
+    }
     Ok(server::ServerConfig {
         addr: matches.value_of("address").unwrap().to_string(),
         port: matches
@@ -62,6 +128,8 @@ fn args_as_config() -> Result<ServerConfig, Error> {
             _ => unreachable!("invalid value for log-level"),
         },
         root: matches.value_of("root").unwrap().to_string(),
+        engine_config,
+        storage: matches.value_of("storage").map(ToString::to_string),
     })
 }
 
@@ -1,22 +1,82 @@
-use crate::{templates::TEMPLATES, Error};
-use engine::board::{Color, Coord, Piece};
+use crate::{
+    templates::{Actions, DrawOffer, Takeback, TEMPLATES},
+    Error,
+};
+use engine::board::{Board, Color, Coord, Piece, CHESS960_POSITIONS};
 use engine::chessmatch::{MatchInfos, MatchRegistry};
-use engine::moves::MoveType;
-use std::lazy::SyncLazy;
+use engine::clock::{Bonus, TimeControl};
+use engine::decision::{Config, SearchResult};
+use engine::moves::{LongMoveList, Move, MoveType};
+use engine::random::Rng;
+use engine::skill::Skill;
+use engine::storage::{JsonFileStorage, StorageError, StorageErrorHandler};
+use engine::variant::Variant;
+use std::collections::HashMap;
+use std::lazy::{SyncLazy, SyncOnceCell};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use rocket::config::{Environment, LoggingLevel};
 use rocket::request::Form;
 use rocket::response::{content::Html, status::NotFound, Redirect};
 use rocket::Request;
+use serde::{Deserialize, Serialize};
 
-static MATCH_REGISTRY: SyncLazy<MatchRegistry<Match>> = SyncLazy::new(|| MatchRegistry::new());
+/// The matches of the server, which are created at launch and loaded from the storage
+static MATCH_REGISTRY: SyncOnceCell<MatchRegistry<Match>> = SyncOnceCell::new();
 
-#[derive(Debug, Clone)]
+/// The time the analysis of a position may take, the page is shown once it is finished
+const ANALYSIS_TIME: Duration = Duration::from_secs(2);
+
+/// The last analysis of every match together with the position in FEN, so reloading the
+/// page does not search again
+static ANALYSES: SyncLazy<Mutex<HashMap<u32, (String, Vec<SearchResult>)>>> =
+    SyncLazy::new(|| Mutex::new(HashMap::new()));
+
+fn registry() -> &'static MatchRegistry<Match> {
+    MATCH_REGISTRY
+        .get()
+        .expect("the match registry is created at launch")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Match {
     host_color: engine::board::Color,
     white_human: bool,
     black_human: bool,
+    white_skill: Skill,
+    black_skill: Skill,
+    /// Seeds the random choices of the computer, so the match can be replayed
+    seed: u64,
+    /// The number of the Chess960 starting position or `None` for the classical one
+    chess960: Option<u32>,
+    variant: Variant,
+}
+
+impl Match {
+    /// The configuration of the computer playing `color` or `None` if a human plays it
+    fn computer_config(&self, color: Color) -> Option<Config> {
+        let (human, skill) = match color {
+            Color::White => (self.white_human, self.white_skill),
+            Color::Black => (self.black_human, self.black_skill),
+        };
+        if human {
+            return None;
+        }
+        let mut config = registry().get_config();
+        skill.apply(&mut config);
+        config.seed = Some(self.seed);
+        Some(config)
+    }
+
+    fn details(&self) -> String {
+        let start = match self.chess960 {
+            Some(n) => format!(", Chess960 position {}", n),
+            None => String::new(),
+        };
+        format!("Rules: {}{}, seed {}", self.variant, start, self.seed)
+    }
 }
 
 #[derive(FromForm, UriDisplayQuery, Debug, Clone)]
@@ -24,6 +84,32 @@ pub struct MatchCreationForm {
     human1: bool,
     human2: bool,
     hostcolor: bool,
+    skill1: String,
+    skill2: String,
+    variant: String,
+    start: String,
+    /// The number of the Chess960 starting position, a random one if empty
+    position: String,
+    /// The base time in minutes, no clock if empty
+    minutes: String,
+    /// The bonus per move in seconds
+    bonus: String,
+    /// Either `increment` or `delay`
+    bonustype: String,
+}
+
+impl MatchCreationForm {
+    fn time_control(&self) -> Option<TimeControl> {
+        let minutes: u64 = self.minutes.trim().parse().ok().filter(|&m| m > 0)?;
+        let bonus = Duration::from_secs(self.bonus.trim().parse().unwrap_or(0));
+        Some(TimeControl {
+            base: Duration::from_secs(minutes * 60),
+            bonus: match self.bonustype.as_str() {
+                "delay" => Bonus::Delay(bonus),
+                _ => Bonus::Increment(bonus),
+            },
+        })
+    }
 }
 
 #[catch(500)]
@@ -43,14 +129,39 @@ fn index() -> Html<String> {
 
 #[post("/match", data = "<desc>")]
 fn new_match(desc: Form<MatchCreationForm>) -> Redirect {
-    let id = MATCH_REGISTRY.create_match(Match {
-        host_color: match desc.hostcolor {
-            true => engine::board::Color::White,
-            false => engine::board::Color::Black,
+    let random_position = || (Rng::from_time().next_u64() % CHESS960_POSITIONS as u64) as u32;
+    let position = desc.position.trim().parse().ok();
+    let chess960 = match desc.start.as_str() {
+        "chess960" => Some(position.unwrap_or_else(random_position)),
+        _ => None,
+    };
+    let mut board = match chess960.map(Board::chess960) {
+        Some(None) => return Redirect::to("/"),
+        Some(Some(board)) => board,
+        None => Board::new(),
+    };
+    let variant = desc.variant.parse().unwrap_or_default();
+    board.set_variant(variant);
+    let id = registry().create_match_from(
+        board,
+        desc.time_control(),
+        Match {
+            host_color: match desc.hostcolor {
+                true => engine::board::Color::White,
+                false => engine::board::Color::Black,
+            },
+            white_human: !desc.human1,
+            black_human: !desc.human2,
+            white_skill: desc.skill1.parse().unwrap_or_default(),
+            black_skill: desc.skill2.parse().unwrap_or_default(),
+            seed: registry()
+                .get_config()
+                .seed
+                .unwrap_or_else(|| Rng::from_time().next_u64()),
+            chess960,
+            variant,
         },
-        white_human: !desc.human1,
-        black_human: !desc.human2,
-    });
+    );
     Redirect::to(format!("/match/{}/host", id))
 }
 
@@ -82,6 +193,73 @@ fn parse_coord(coord: &str) -> Option<Coord> {
     Coord::from_str(coord).ok()
 }
 
+/// The piece of the pocket chosen for a drop in Crazyhouse, written like `N@`
+fn parse_drop(piece: &str) -> Option<Piece> {
+    match piece {
+        "Q@" => Some(Piece::Queen),
+        "R@" => Some(Piece::Rook),
+        "B@" => Some(Piece::Bishop),
+        "N@" => Some(Piece::Knight),
+        "P@" => Some(Piece::Pawn),
+        _ => None,
+    }
+}
+
+fn find_drop(board: &Board, color: Color, piece: Piece, to: Coord) -> Option<Move> {
+    let mut moves = LongMoveList::new();
+    board.enumerate_all_moves_by(color, &mut moves);
+    moves
+        .slice()
+        .iter()
+        .copied()
+        .find(|mv| mv.end == to && mv.move_type == MoveType::Drop(piece))
+}
+
+/// The color of the human `user` or `None` for spectators and computers
+fn user_color(user: UserType, info: &MatchInfos<Match>) -> Option<Color> {
+    let color = match user {
+        UserType::Host => info.extra.host_color,
+        UserType::Player => !info.extra.host_color,
+        UserType::Spectator => return None,
+    };
+    match color {
+        Color::White if info.extra.white_human => Some(color),
+        Color::Black if info.extra.black_human => Some(color),
+        _ => None,
+    }
+}
+
+/// The half moves back to the last move of `color`, which a takeback of it undoes
+fn takeback_plies(color: Color, info: &MatchInfos<Match>) -> Option<usize> {
+    let plies = if info.color == color { 2 } else { 1 };
+    if info.result.is_none() && plies <= info.history.len() {
+        Some(plies)
+    } else {
+        None
+    }
+}
+
+fn get_actions(user: UserType, info: &MatchInfos<Match>) -> Actions {
+    let color = match (user_color(user, info), info.result) {
+        (Some(color), None) => color,
+        _ => return Actions::NONE,
+    };
+    Actions {
+        resign: true,
+        draw: match info.draw_offer {
+            Some(offer) if offer == color => DrawOffer::Waiting,
+            Some(_) => DrawOffer::Answer,
+            None => DrawOffer::Available,
+        },
+        takeback: match info.takeback {
+            Some(request) if request.color == color => Takeback::Waiting,
+            Some(request) => Takeback::Answer(request.plies),
+            None if takeback_plies(color, info).is_some() => Takeback::Available,
+            None => Takeback::Unavailable,
+        },
+    }
+}
+
 fn color_to_move(user: UserType, info: &MatchInfos<Match>) -> bool {
     matches!((user, info.color, info.extra.host_color, info.extra.white_human, info.extra.black_human),
         (UserType::Host, Color::White, Color::White, true, _)
@@ -99,17 +277,20 @@ fn view_match(
     id: u32,
     userstr: String,
 ) -> Result<Html<String>, NotFound<Html<String>>> {
-    let reg = SyncLazy::force(&MATCH_REGISTRY);
+    let reg = registry();
     let board = reg.get_board(id).ok_or_else(|| not_found(req.0))?;
     let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
     Ok(Html(
         TEMPLATES
             .get_chessboard(
-                format!("/match/{}/{}", id, userstr),
+                id,
+                &userstr,
                 "",
                 &board,
-                info.result,
+                &info,
+                &info.extra.details(),
                 None,
+                get_user(&userstr, req.0).map_or(Actions::NONE, |user| get_actions(user, &info)),
             )
             .unwrap(),
     ))
@@ -123,8 +304,12 @@ fn view_match_select(
     coordstr: String,
 ) -> Result<Html<String>, NotFound<Html<String>>> {
     let user = get_user(&userstr, req.0)?;
-    let coord = parse_coord(&coordstr).ok_or_else(|| not_found(&req.0))?;
-    let reg = SyncLazy::force(&MATCH_REGISTRY);
+    let drop = parse_drop(&coordstr);
+    let coord = parse_coord(&coordstr);
+    if drop.is_none() && coord.is_none() {
+        return Err(not_found(&req.0));
+    }
+    let reg = registry();
     let board = reg.get_board(id).ok_or_else(|| not_found(&req.0))?;
     let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
     let moves = if color_to_move(user, &info) {
@@ -132,32 +317,184 @@ fn view_match_select(
             let (x, y) = m.as_xy();
             y * 8 + x
         };
-        Some((
-            flatten(coord),
-            board
+        let targets = match (drop, coord) {
+            (Some(piece), _) => {
+                let mut moves = LongMoveList::new();
+                board.enumerate_all_moves_by(info.color, &mut moves);
+                moves
+                    .slice()
+                    .iter()
+                    .filter(|m| m.move_type == MoveType::Drop(piece))
+                    .map(|m| flatten(m.end))
+                    .collect()
+            }
+            (None, Some(coord)) => board
                 .enumerate_moves(info.color, coord)
                 .slice()
                 .iter()
                 .filter(|m| board.get(m.start).is_color_piece_include_king(!info.color))
-                .map(|m| flatten(m.end))
+                // castling is also chosen with the rook, as the king may not move at all
+                .flat_map(|m| match m.move_type {
+                    MoveType::Castle(castle) if m.end == m.start => vec![flatten(castle.rook_pos)],
+                    MoveType::Castle(castle) => vec![flatten(m.end), flatten(castle.rook_pos)],
+                    _ => vec![flatten(m.end)],
+                })
                 .collect(),
-        ))
+            (None, None) => vec![],
+        };
+        Some((coord.map_or(-1, flatten), targets))
     } else {
         None
     };
     Ok(Html(
         TEMPLATES
             .get_chessboard(
-                format!("/match/{}/{}", id, userstr),
+                id,
+                &userstr,
                 &coordstr,
                 &board,
-                info.result,
+                &info,
+                &info.extra.details(),
                 moves,
+                get_actions(user, &info),
             )
             .unwrap(),
     ))
 }
 
+/// Take back the last move of the user, which a human opponent has to approve
+#[get("/takeback/<id>/<userstr>")]
+fn takeback(
+    req: RequestWrap,
+    id: u32,
+    userstr: String,
+) -> Result<Redirect, NotFound<Html<String>>> {
+    let user = get_user(&userstr, req.0)?;
+    let reg = registry();
+    let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
+    let color = user_color(user, &info).ok_or_else(|| not_found(&req.0))?;
+    let plies = takeback_plies(color, &info).ok_or_else(|| not_found(&req.0))?;
+    let result = if info.extra.computer_config(!color).is_some() {
+        reg.undo(id, plies)
+    } else {
+        reg.request_undo(id, color, plies)
+    };
+    result.map_err(|_| not_found(&req.0))?;
+    Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
+}
+
+#[get("/takeback/<id>/<userstr>/<answer>")]
+fn takeback_answer(
+    req: RequestWrap,
+    id: u32,
+    userstr: String,
+    answer: String,
+) -> Result<Redirect, NotFound<Html<String>>> {
+    let user = get_user(&userstr, req.0)?;
+    let approve = match answer.as_str() {
+        "accept" => true,
+        "decline" => false,
+        _ => return Err(not_found(&req.0)),
+    };
+    let reg = registry();
+    let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
+    let color = user_color(user, &info).ok_or_else(|| not_found(&req.0))?;
+    reg.answer_undo(id, color, approve)
+        .map_err(|_| not_found(&req.0))?;
+    Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
+}
+
+#[get("/resign/<id>/<userstr>")]
+fn resign(req: RequestWrap, id: u32, userstr: String) -> Result<Redirect, NotFound<Html<String>>> {
+    let user = get_user(&userstr, req.0)?;
+    let reg = registry();
+    let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
+    let color = user_color(user, &info).ok_or_else(|| not_found(&req.0))?;
+    reg.resign(id, color).map_err(|_| not_found(&req.0))?;
+    Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
+}
+
+/// Offer a draw, which a computer opponent accepts or declines after thinking about it
+#[get("/draw/<id>/<userstr>")]
+fn draw(req: RequestWrap, id: u32, userstr: String) -> Result<Redirect, NotFound<Html<String>>> {
+    let user = get_user(&userstr, req.0)?;
+    let reg = registry();
+    let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
+    let color = user_color(user, &info).ok_or_else(|| not_found(&req.0))?;
+    reg.offer_draw(id, color, info.extra.computer_config(!color))
+        .map_err(|_| not_found(&req.0))?;
+    Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
+}
+
+#[get("/draw/<id>/<userstr>/<answer>")]
+fn draw_answer(
+    req: RequestWrap,
+    id: u32,
+    userstr: String,
+    answer: String,
+) -> Result<Redirect, NotFound<Html<String>>> {
+    let user = get_user(&userstr, req.0)?;
+    let accept = match answer.as_str() {
+        "accept" => true,
+        "decline" => false,
+        _ => return Err(not_found(&req.0)),
+    };
+    let reg = registry();
+    let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
+    let color = user_color(user, &info).ok_or_else(|| not_found(&req.0))?;
+    reg.answer_draw(id, color, accept)
+        .map_err(|_| not_found(&req.0))?;
+    Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
+}
+
+#[get("/eval/<id>/<userstr>")]
+fn view_eval(
+    req: RequestWrap,
+    id: u32,
+    userstr: String,
+) -> Result<Html<String>, NotFound<Html<String>>> {
+    let reg = registry();
+    let board = reg.get_board(id).ok_or_else(|| not_found(req.0))?;
+    let trace = engine::decision::evaluate_explained(&board, &reg.get_config().eval);
+    Ok(Html(
+        TEMPLATES
+            .get_eval(format!("/match/{}/{}", id, userstr), &board, &trace)
+            .unwrap(),
+    ))
+}
+
+#[get("/analysis/<id>/<userstr>")]
+fn view_analysis(
+    req: RequestWrap,
+    id: u32,
+    userstr: String,
+) -> Result<Html<String>, NotFound<Html<String>>> {
+    let reg = registry();
+    let board = reg.get_board(id).ok_or_else(|| not_found(req.0))?;
+    let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
+    let fen = board.to_fen(info.color);
+    let cached = match ANALYSES.lock().unwrap().get(&id) {
+        Some((position, results)) if *position == fen => Some(results.clone()),
+        _ => None,
+    };
+    let results = cached.unwrap_or_else(|| {
+        let mut config = reg.get_config();
+        config.move_time = Some(
+            config
+                .move_time
+                .map_or(ANALYSIS_TIME, |t| t.min(ANALYSIS_TIME)),
+        );
+        let results = engine::decision::analyze(&board, info.color, config, None);
+        ANALYSES.lock().unwrap().insert(id, (fen, results.clone()));
+        results
+    });
+    Ok(Html(
+        TEMPLATES
+            .get_analysis(format!("/match/{}/{}", id, userstr), info.color, &results)
+            .unwrap(),
+    ))
+}
+
 #[get("/match/<id>/<userstr>/<fromstr>/to/<tostr>/promote")]
 fn promotion_get(id: u32, userstr: String, fromstr: String, tostr: String) -> Html<String> {
     Html(
@@ -190,11 +527,10 @@ fn promotion_push(
         "rook" => Piece::Rook,
         "knight" => Piece::Knight,
         "bishop" => Piece::Bishop,
-        "pawn" => Piece::Pawn,
         _ => return Err(rb()),
     };
     let user = get_user(&userstr, req.0).map_err(|_| rb())?;
-    let reg = SyncLazy::force(&MATCH_REGISTRY);
+    let reg = registry();
     let info = reg.get_info(id).ok_or_else(rb)?;
     if color_to_move(user, &info) {
         let board = reg.get_board(id).ok_or_else(rb)?;
@@ -214,12 +550,7 @@ fn promotion_push(
             &[mv] => mv,
             multi => multi[0],
         };
-        reg.do_move(
-            id,
-            mv,
-            (!info.extra.white_human && info.color == Color::Black)
-                || (!info.extra.black_human && info.color == Color::White),
-        );
+        reg.do_move(id, mv, info.extra.computer_config(!info.color));
         Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
     } else {
         Err(rb())
@@ -235,14 +566,27 @@ fn make_move(
     tostr: String,
 ) -> Result<Redirect, NotFound<Html<String>>> {
     let user = get_user(&userstr, req.0)?;
-    let reg = SyncLazy::force(&MATCH_REGISTRY);
+    let reg = registry();
     let info = reg.get_info(id).ok_or_else(|| not_found(&req.0))?;
     if color_to_move(user, &info) {
         let board = reg.get_board(id).ok_or_else(|| not_found(&req.0))?;
-        let from = parse_coord(&fromstr).ok_or_else(|| not_found(&req.0))?;
         let to = parse_coord(&tostr).ok_or_else(|| not_found(&req.0))?;
+        if let Some(piece) = parse_drop(&fromstr) {
+            let mv = find_drop(&board, info.color, piece, to).ok_or_else(|| not_found(&req.0))?;
+            reg.do_move(id, mv, info.extra.computer_config(!info.color));
+            return Ok(Redirect::to(format!("/match/{}/{}", id, userstr)));
+        }
+        let from = parse_coord(&fromstr).ok_or_else(|| not_found(&req.0))?;
         let mut moves = board.enumerate_moves(info.color, from);
-        moves.filter(0, |mv| mv.end == to);
+        moves.filter(0, |mv| match mv.move_type {
+            MoveType::Castle(castle) => mv.end == to || castle.rook_pos == to,
+            _ => mv.end == to,
+        });
+        // a king that castles onto a square it could also step to castles by choosing the rook
+        let is_castle = |mv: &Move| matches!(mv.move_type, MoveType::Castle(_));
+        if !moves.slice().iter().all(is_castle) {
+            moves.filter(0, |mv| !is_castle(mv));
+        }
         let mv = match moves.slice() {
             [] => return Err(not_found(&req.0)),
             &[mv] => mv,
@@ -260,12 +604,7 @@ fn make_move(
                 }
             }
         };
-        reg.do_move(
-            id,
-            mv,
-            (!info.extra.white_human && info.color == Color::Black)
-                || (!info.extra.black_human && info.color == Color::White),
-        );
+        reg.do_move(id, mv, info.extra.computer_config(!info.color));
         Ok(Redirect::to(format!("/match/{}/{}", id, userstr)))
     } else {
         Err(not_found(req.0))
@@ -278,9 +617,33 @@ pub struct ServerConfig {
     pub port: u16,
     pub log_level: LoggingLevel,
     pub root: String,
+    pub engine_config: engine::decision::Config,
+    /// The directory the matches are kept in across restarts
+    pub storage: Option<String>,
 }
 
 pub fn launch(config: ServerConfig) -> Error {
+    let log_level = config.log_level;
+    // a match that cannot be stored goes on, it only loses its latest changes on a restart
+    let on_error: StorageErrorHandler = Arc::new(move |id: u32, e: StorageError| {
+        if !matches!(log_level, LoggingLevel::Off) {
+            eprintln!("match {}: {}", id, e);
+        }
+    });
+    let registry = match config.storage {
+        Some(dir) => match JsonFileStorage::open(dir)
+            .and_then(|storage| MatchRegistry::with_storage(Arc::new(storage), on_error))
+        {
+            Err(e) => return Error::StorageError(e),
+            Ok(v) => v,
+        },
+        None => MatchRegistry::new(),
+    };
+    registry.set_config(config.engine_config);
+    if MATCH_REGISTRY.set(registry).is_err() {
+        panic!("the server was already launched");
+    }
+    registry().resume_computers(Match::computer_config);
     let app = rocket::custom(
         match rocket::config::Config::build(Environment::Production)
             .address(config.addr)
@@ -301,10 +664,17 @@ pub fn launch(config: ServerConfig) -> Error {
                     new_match,
                     view_match,
                     view_match_select,
+                    view_eval,
+                    view_analysis,
                     favicon,
                     make_move,
                     promotion_get,
-                    promotion_push
+                    promotion_push,
+                    takeback,
+                    takeback_answer,
+                    resign,
+                    draw,
+                    draw_answer
                 ],
             )
             .launch(),
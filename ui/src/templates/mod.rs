@@ -1,15 +1,61 @@
 use crate::Error;
 use engine::board::{Field, Piece};
-use engine::chessmatch::MatchResult;
+use engine::chessmatch::{MatchInfos, MatchResult};
+use engine::clock::Clock;
+use engine::crazyhouse::POCKET_PIECES;
+use engine::decision::SearchResult;
+use engine::score::EvalTrace;
+use engine::skill::{Skill, SKILLS};
+use engine::variant::{Variant, VARIANTS};
 use std::lazy::SyncLazy;
 
 pub(crate) static TEMPLATES: SyncLazy<Templates> = SyncLazy::new(|| Templates::new().unwrap());
 
+/// What the user may do about taking back moves
+#[derive(Debug, Clone, Copy)]
+pub enum Takeback {
+    Unavailable,
+    Available,
+    /// The user asked the opponent to approve
+    Waiting,
+    /// The opponent asks to take back this many half moves
+    Answer(usize),
+}
+
+/// What the user may do about a draw
+#[derive(Debug, Clone, Copy)]
+pub enum DrawOffer {
+    Unavailable,
+    Available,
+    /// The user offered a draw
+    Waiting,
+    /// The opponent offered a draw
+    Answer,
+}
+
+/// What the user may do besides moving
+#[derive(Debug, Clone, Copy)]
+pub struct Actions {
+    pub resign: bool,
+    pub draw: DrawOffer,
+    pub takeback: Takeback,
+}
+
+impl Actions {
+    pub const NONE: Self = Self {
+        resign: false,
+        draw: DrawOffer::Unavailable,
+        takeback: Takeback::Unavailable,
+    };
+}
+
 pub struct Templates {
     base: liquid::Template,
     index: liquid::Template,
     chessboard: liquid::Template,
     promote: liquid::Template,
+    eval: liquid::Template,
+    analysis: liquid::Template,
     s404: liquid::Template,
     s500: liquid::Template,
 }
@@ -23,6 +69,8 @@ impl Templates {
             index: parser.parse(include_str!("index.html")).map_err(err)?,
             chessboard: parser.parse(include_str!("chessboard.html")).map_err(err)?,
             promote: parser.parse(include_str!("promote.html")).map_err(err)?,
+            eval: parser.parse(include_str!("eval.html")).map_err(err)?,
+            analysis: parser.parse(include_str!("analysis.html")).map_err(err)?,
             s404: parser.parse(include_str!("404.html")).map_err(err)?,
             s500: parser.parse(include_str!("500.html")).map_err(err)?,
         })
@@ -33,8 +81,14 @@ impl Templates {
     }
 
     fn get_index_container(&self) -> Result<String, Error> {
+        let skills: Vec<&str> = SKILLS.iter().map(Skill::name).collect();
+        let variants: Vec<&str> = VARIANTS.iter().map(Variant::name).collect();
         self.index
             .render(&liquid::object! {{
+                "skills": skills,
+                "default_skill": Skill::default().name(),
+                "variants": variants,
+                "default_variant": Variant::default().name(),
             }})
             .map_err(Self::parsing_err)
     }
@@ -74,15 +128,8 @@ impl Templates {
         )
     }
 
-    pub fn get_chessboard(
-        &self,
-        rooturi: String,
-        appendix: &str,
-        board: &engine::board::Board,
-        result: Option<MatchResult>,
-        moves: Option<(i8, Vec<i8>)>,
-    ) -> Result<String, Error> {
-        let matrix: Vec<_> = (0..64)
+    fn get_piece_matrix(board: &engine::board::Board) -> Vec<liquid::model::Value> {
+        (0..64)
             .map(|i| {
                 let f = board.get(engine::board::Coord::from_xy(i % 8, i / 8));
                 (match f {
@@ -103,27 +150,288 @@ impl Templates {
             })
             .map(Into::<liquid::model::ScalarCow>::into)
             .map(liquid::model::Value::Scalar)
-            .collect();
+            .collect()
+    }
+
+    /// The pieces in the pockets of both colors, the ones of `color` link to their drops
+    fn get_pockets(
+        board: &engine::board::Board,
+        color: engine::board::Color,
+        rooturi: &str,
+        appendix: &str,
+    ) -> Vec<liquid::model::Value> {
+        let mut pockets = vec![];
+        for &(c, class) in &[
+            (engine::board::Color::White, 0),
+            (engine::board::Color::Black, 1),
+        ] {
+            for &piece in &POCKET_PIECES {
+                let count = board.get_pocket(c).count(piece);
+                if count == 0 {
+                    continue;
+                }
+                let letter = match piece {
+                    Piece::Queen => 'Q',
+                    Piece::Rook => 'R',
+                    Piece::Bishop => 'B',
+                    Piece::Knight => 'N',
+                    Piece::Pawn => 'P',
+                };
+                let selected = c == color && appendix == format!("{}@", letter);
+                let uri = if c == color {
+                    format!("{}/{}@", rooturi, letter)
+                } else {
+                    String::new()
+                };
+                pockets.push(
+                    liquid::model::to_value(&(
+                        class,
+                        piece.unicode().to_string(),
+                        count,
+                        uri,
+                        selected,
+                    ))
+                    .unwrap(),
+                );
+            }
+        }
+        pockets
+    }
+
+    /// The moves of the match in standard algebraic notation, a white and black one per row
+    fn get_move_list<E: Clone + Send + Sync>(info: &MatchInfos<E>) -> Vec<liquid::model::Value> {
+        info.history
+            .chunks(2)
+            .enumerate()
+            .map(|(i, moves)| {
+                let san = |n: usize| moves.get(n).map(|m| m.san.clone()).unwrap_or_default();
+                liquid::model::to_value(&(i + 1, san(0), san(1))).unwrap()
+            })
+            .collect()
+    }
+
+    /// The time left for both colors, counted down on the page while it runs
+    fn get_clocks(clock: Option<&Clock>) -> Vec<liquid::model::Value> {
+        let clock = match clock {
+            Some(clock) => clock,
+            None => return vec![],
+        };
+        [
+            (engine::board::Color::White, "White"),
+            (engine::board::Color::Black, "Black"),
+        ]
+        .iter()
+        .map(|&(color, name)| {
+            let remaining = clock.remaining(color);
+            let secs = (remaining.as_millis() + 999) / 1000;
+            liquid::model::to_value(&(
+                name,
+                remaining.as_millis() as u64,
+                clock.is_running(color),
+                clock.remaining_delay(color).as_millis() as u64,
+                format!("{}:{:02}", secs / 60, secs % 60),
+            ))
+            .unwrap()
+        })
+        .collect()
+    }
+
+    fn result_message(result: MatchResult) -> String {
+        let name = |color| match color {
+            engine::board::Color::White => "White",
+            engine::board::Color::Black => "Black",
+        };
+        match result {
+            MatchResult::WhiteWins => "White wins!".to_string(),
+            MatchResult::BlackWins => "Black wins!".to_string(),
+            MatchResult::Stalemate => "Stalemate!".to_string(),
+            MatchResult::KingOfTheHill(c) => format!("{} wins on the hill!", name(c)),
+            MatchResult::ThreeChecks(c) => format!("{} wins with the third check!", name(c)),
+            MatchResult::Explosion(c) => format!("{} wins by exploding the king!", name(c)),
+            MatchResult::OutOfMoves(c) => format!("{} wins without moves left!", name(c)),
+            MatchResult::Timeout(c) => format!("{} wins on time!", name(c)),
+            MatchResult::Resignation(c) => format!("{} wins by resignation!", name(c)),
+            MatchResult::DrawByAgreement => "Draw by agreement!".to_string(),
+        }
+    }
+
+    pub fn get_chessboard<E: Clone + Send + Sync>(
+        &self,
+        id: u32,
+        userstr: &str,
+        appendix: &str,
+        board: &engine::board::Board,
+        info: &MatchInfos<E>,
+        details: &str,
+        moves: Option<(i8, Vec<i8>)>,
+        actions: Actions,
+    ) -> Result<String, Error> {
+        let search = info.last_search.as_ref();
+        let matrix = Self::get_piece_matrix(board);
+        let rooturi = format!("/match/{}/{}", id, userstr);
+        let pockets = Self::get_pockets(board, info.color, &rooturi, appendix);
+        let clocks = Self::get_clocks(info.clock.as_ref());
+        let movelist = Self::get_move_list(info);
+        let draw = match actions.draw {
+            DrawOffer::Unavailable => "",
+            DrawOffer::Available => "available",
+            DrawOffer::Waiting => "waiting",
+            DrawOffer::Answer => "answer",
+        };
+        let (takeback, takebackplies) = match actions.takeback {
+            Takeback::Unavailable => ("", 0),
+            Takeback::Available => ("available", 0),
+            Takeback::Waiting => ("waiting", 0),
+            Takeback::Answer(plies) => ("answer", plies),
+        };
+        let time_control = info
+            .clock
+            .as_ref()
+            .map(|c| c.control.to_string())
+            .unwrap_or_default();
         let mut movematrix: Vec<bool> = core::iter::repeat(false).take(64).collect();
         for &coord in moves.iter().map(|i| i.1.iter()).flatten() {
             movematrix[coord as usize] = true;
         }
         let xy = moves.map(|(v, _)| v).unwrap_or(-1);
+        let pv = search
+            .map(|s| {
+                let pv: Vec<String> = s.pv.iter().map(ToString::to_string).collect();
+                pv.join(" ")
+            })
+            .unwrap_or_default();
+        let searchinfo = search
+            .map(|s| {
+                if s.from_book {
+                    return "book move".to_string();
+                }
+                if s.from_tablebase {
+                    return format!("tablebase move, score {}", s.score);
+                }
+                format!(
+                    "score {}, depth {}/{}, {} nodes in {} ms",
+                    s.score,
+                    s.depth,
+                    s.seldepth,
+                    s.nodes,
+                    s.elapsed.as_millis()
+                )
+            })
+            .unwrap_or_default();
+        let thinking = info
+            .thinking
+            .as_ref()
+            .map(|p| {
+                let pv: Vec<String> = p.pv.iter().map(ToString::to_string).collect();
+                format!(
+                    "depth {}/{}, score {}, {} nodes, {} nodes/s: {}",
+                    p.depth,
+                    p.seldepth,
+                    p.score,
+                    p.nodes,
+                    p.nps,
+                    pv.join(" ")
+                )
+            })
+            .unwrap_or_default();
         self.get_base(
             "Chess Match",
             self.chessboard
                 .render(&liquid::object! {{
                     "matrix": liquid::model::Value::Array(matrix),
                     "rooturi": rooturi,
+                    "evaluri": format!("/eval/{}/{}", id, userstr),
+                    "analysisuri": format!("/analysis/{}/{}", id, userstr),
                     "appendix": appendix,
                     "fcoord": xy,
-                    "status": match result {
-                        None => 0,
-                        Some(MatchResult::WhiteWins) => 1,
-                        Some(MatchResult::BlackWins) => 2,
-                        Some(MatchResult::Stalemate) => 3,
-                    },
+                    "result": info.result.map(Self::result_message).unwrap_or_default(),
                     "moves": movematrix,
+                    "pockets": liquid::model::Value::Array(pockets),
+                    "clocks": liquid::model::Value::Array(clocks),
+                    "timecontrol": time_control,
+                    "movelist": liquid::model::Value::Array(movelist),
+                    "takebackuri": format!("/takeback/{}/{}", id, userstr),
+                    "takeback": takeback,
+                    "takebackplies": takebackplies,
+                    "drawuri": format!("/draw/{}/{}", id, userstr),
+                    "draw": draw,
+                    "resignuri": format!("/resign/{}/{}", id, userstr),
+                    "resign": actions.resign,
+                    "pv": pv,
+                    "searchinfo": searchinfo,
+                    "thinking": thinking,
+                    "details": details,
+                }})
+                .map_err(Self::parsing_err),
+        )
+    }
+
+    pub fn get_analysis(
+        &self,
+        matchuri: String,
+        color: engine::board::Color,
+        results: &[SearchResult],
+    ) -> Result<String, Error> {
+        let candidates: Vec<liquid::model::Value> = results
+            .iter()
+            .map(|r| {
+                let pv: Vec<String> = r.pv.iter().map(ToString::to_string).collect();
+                liquid::model::to_value(&(
+                    r.best_move.to_string(),
+                    r.score.to_string(),
+                    pv.join(" "),
+                ))
+                .unwrap()
+            })
+            .collect();
+        self.get_base(
+            "Chess Match - Analysis",
+            self.analysis
+                .render(&liquid::object! {{
+                    "matchuri": matchuri,
+                    "color": match color {
+                        engine::board::Color::White => "White",
+                        engine::board::Color::Black => "Black",
+                    },
+                    "candidates": liquid::model::Value::Array(candidates),
+                    "depth": results.first().map_or(0, |r| r.depth),
+                }})
+                .map_err(Self::parsing_err),
+        )
+    }
+
+    pub fn get_eval(
+        &self,
+        matchuri: String,
+        board: &engine::board::Board,
+        trace: &EvalTrace,
+    ) -> Result<String, Error> {
+        let terms = |name: &str, f: fn(&engine::score::EvalTerms) -> i32| {
+            let (w, b) = (f(&trace.white), f(&trace.black));
+            liquid::model::to_value(&(name, w, b, w - b)).unwrap()
+        };
+        let net: Vec<i32> = trace
+            .squares
+            .iter()
+            .map(|s| s.white.total() - s.black.total())
+            .collect();
+        self.get_base(
+            "Chess Match - Evaluation",
+            self.eval
+                .render(&liquid::object! {{
+                    "matchuri": matchuri,
+                    "matrix": liquid::model::Value::Array(Self::get_piece_matrix(board)),
+                    "terms": liquid::model::Value::Array(vec![
+                        terms("Material", |t| t.material),
+                        terms("Threat bounty", |t| t.threat_bounty),
+                        terms("Centralization", |t| t.centralization),
+                        terms("Border penalty", |t| t.border_penalty),
+                        terms("Variant", |t| t.variant),
+                        terms("Total", engine::score::EvalTerms::total),
+                    ]),
+                    "net": net,
+                    "score": trace.score(),
+                    "endgame": trace.endgame.is_some(),
                 }})
                 .map_err(Self::parsing_err),
         )